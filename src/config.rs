@@ -1,11 +1,16 @@
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use colored::Colorize;
 use log::{info, LevelFilter};
 
+use crate::database::Credentials;
+
 /// Command line arguments
 #[derive(Parser, Debug)]
 #[command(about)]
 pub struct Args {
+    /// Maintenance command to run instead of the indexer
+    #[command(subcommand)]
+    pub command: Option<Command>,
     /// Certificate to check jetstream server against
     #[arg(short = 'c', long, default_value = "/etc/ssl/certs/ISRG_Root_X1.pem")]
     pub certificate: String,
@@ -15,15 +20,159 @@ pub struct Args {
     /// Override amount of concurrent requests the full indexer may handle
     #[arg(long)]
     pub max_concurrent_requests: Option<usize>,
-    /// Endpoint of the database server (including port and protocol)
+    /// Maximum amount of bytes the full indexer caches per repo for blocks
+    /// arriving before the tree node referencing them
+    #[arg(long, default_value_t = 16 * 1024 * 1024)]
+    pub repo_block_cache: usize,
+    /// PLC directory to resolve did:plc identities with
+    #[arg(long, default_value = "https://plc.directory")]
+    pub plc_directory: String,
+    /// Seconds resolved DID documents are cached in the database
+    #[arg(long, value_name = "SECONDS", default_value_t = 86400)]
+    pub did_cache_ttl: u64,
+    /// Sources the full indexer discovers repos from (may be repeated)
+    #[arg(long, value_enum, default_values_t = [BackfillSeed::Follows])]
+    pub backfill_seed: Vec<BackfillSeed>,
+    /// File with one DID per line for the file backfill seed
+    #[arg(long, value_name = "PATH")]
+    pub seed_file: Option<String>,
+    /// PDS to enumerate for the list-repos backfill seed (may be repeated)
+    #[arg(long, value_name = "URL")]
+    pub seed_pds: Vec<String>,
+    /// Re-crawl repos the full indexer indexed longer ago than this
+    #[arg(long, value_name = "SECONDS")]
+    pub recrawl_after: Option<u64>,
+    /// Re-crawl repos whose indexed revision is behind the one seen on the firehose
+    #[arg(long)]
+    pub recrawl_lagging: bool,
+    /// Endpoint of the database server (including port and protocol), one of
+    /// mem://, rocksdb://<path>, surrealkv://<path>, tikv://<pd host:port>
+    /// or ws[s]://<host:port> and http[s]://<host:port>
     #[arg(short = 'D', long, default_value = "rocksdb://path/to/surreal.db")]
     pub db: String,
+    /// Database namespace to use
+    #[arg(long, env = "SURREAL_NS", default_value = "atp")]
+    pub db_namespace: String,
+    /// Database to use
+    #[arg(long, env = "SURREAL_DB", default_value = "atp")]
+    pub db_database: String,
+    /// Username to sign in to the database server with
+    #[arg(long, env = "SURREAL_USER")]
+    pub db_username: Option<String>,
+    /// Password to sign in to the database server with
+    #[arg(long, env = "SURREAL_PASS", hide_env_values = true)]
+    pub db_password: Option<String>,
+    /// Level the database user is defined on
+    #[arg(long, env = "SURREAL_AUTH_LEVEL", value_enum, default_value_t = AuthLevel::Root)]
+    pub db_auth_level: AuthLevel,
+    /// Token to authenticate with instead of a username and password
+    #[arg(long, env = "SURREAL_TOKEN", hide_env_values = true, conflicts_with_all = ["db_username", "db_password"])]
+    pub db_token: Option<String>,
     /// Debug verbosity level
     #[arg(short, action = ArgAction::Count)]
     pub verbosity: u8,
     /// Indexer Mode (jetstream only or full)
     #[arg(long, default_value = "jetstream")]
     pub mode: String,
+    /// What to do with likes, reposts, quotes and replies of a deleted post
+    #[arg(long, value_enum, default_value_t = PostDeleteCascade::Orphan)]
+    pub post_delete_cascade: PostDeleteCascade,
+    /// Skip indexes that are expensive to build and maintain (for small deployments)
+    #[arg(long)]
+    pub skip_expensive_indexes: bool,
+    /// Skip a specific index by name (may be repeated)
+    #[arg(long, value_name = "NAME")]
+    pub disable_index: Vec<String>,
+}
+
+/// Maintenance commands
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Recompute all engagement counters from the edges and exit
+    RepairCounters,
+    /// Manage the database schema migrations
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Run a full-text search against the database
+    Search {
+        #[command(subcommand)]
+        target: SearchTarget,
+    },
+}
+
+/// Full-text search commands
+#[derive(Subcommand, Debug)]
+pub enum SearchTarget {
+    /// Search posts by text
+    Posts {
+        /// Search terms
+        query: String,
+        /// Language of the search terms, enables stemming where supported
+        #[arg(long)]
+        lang: Option<String>,
+        /// Maximum number of results
+        #[arg(long, default_value_t = 25)]
+        limit: usize,
+    },
+    /// Search profiles by display name and description
+    Profiles {
+        /// Search terms
+        query: String,
+        /// Maximum number of results
+        #[arg(long, default_value_t = 25)]
+        limit: usize,
+    },
+}
+
+/// Schema migration commands
+#[derive(Subcommand, Debug)]
+pub enum MigrateAction {
+    /// Apply all pending migrations
+    Up {
+        /// Print the SurrealQL that would be executed instead of running it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show the schema version and pending migrations
+    Status,
+}
+
+/// Level a database user is defined on
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthLevel {
+    /// Root user with access to all namespaces
+    Root,
+    /// User defined on the namespace
+    Namespace,
+    /// User defined on the database
+    Database,
+}
+
+/// Source of DIDs for the full repo indexer
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackfillSeed {
+    /// Accounts found in the follow graph
+    Follows,
+    /// DIDs listed in the file given by --seed-file
+    File,
+    /// Repositories enumerated from the servers given by --seed-pds
+    ListRepos,
+    /// Operation log exported by the PLC directory
+    PlcExport,
+}
+
+/// Cascade policy for records referencing a deleted post
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PostDeleteCascade {
+    /// Mark referencing edges as orphaned
+    #[default]
+    Orphan,
+    /// Delete referencing edges
+    Delete,
+    /// Leave referencing edges untouched
+    Keep,
 }
 
 impl Args {
@@ -48,13 +197,89 @@ impl Args {
                 |v| v.to_string().green()
             )
         );
+        info!(
+            "{}: {}",
+            "Repo block cache".cyan(),
+            format!("{} bytes", self.repo_block_cache).green()
+        );
+        info!("{}: {}", "PLC Directory".cyan(), self.plc_directory.green());
+        info!(
+            "{}: {}",
+            "DID cache TTL".cyan(),
+            format!("{}s", self.did_cache_ttl).green()
+        );
+        info!(
+            "{}: {}",
+            "Backfill seeds".cyan(),
+            format!("{:?}", self.backfill_seed).green()
+        );
+        info!(
+            "{}: {}",
+            "Re-crawl".cyan(),
+            match (self.recrawl_after, self.recrawl_lagging) {
+                (Some(age), true) => format!("after {}s or when lagging", age).green(),
+                (Some(age), false) => format!("after {}s", age).green(),
+                (None, true) => "when lagging".green(),
+                (None, false) => "Never".yellow(),
+            }
+        );
         info!("{}: {}", "Database".cyan(), self.db.green());
+        info!(
+            "{}: {}/{}",
+            "Database Namespace/Name".cyan(),
+            self.db_namespace.green(),
+            self.db_database.green()
+        );
+        info!(
+            "{}: {}",
+            "Database Authentication".cyan(),
+            match (&self.db_token, &self.db_username) {
+                (Some(_), _) => "Token".green(),
+                (None, Some(username)) =>
+                    format!("{} ({:?} level)", username, self.db_auth_level).green(),
+                (None, None) => "None".yellow(),
+            }
+        );
         info!(
             "{}: {}",
             "Verbosity Level".cyan(),
             self.log_level().to_string().green()
         );
         info!("{}: {}", "Mode".cyan(), self.mode.green());
+        info!(
+            "{}: {}",
+            "Post Delete Cascade".cyan(),
+            format!("{:?}", self.post_delete_cascade).green()
+        );
+        info!(
+            "{}: {}",
+            "Skip Expensive Indexes".cyan(),
+            self.skip_expensive_indexes.to_string().green()
+        );
+        if !self.disable_index.is_empty() {
+            info!(
+                "{}: {}",
+                "Disabled Indexes".cyan(),
+                self.disable_index.join(", ").green()
+            );
+        }
+    }
+
+    /// Credentials to authenticate with the database server
+    pub fn db_credentials(self: &Self) -> anyhow::Result<Credentials> {
+        if let Some(token) = &self.db_token {
+            return Ok(Credentials::Token(token.clone()));
+        }
+
+        match (&self.db_username, &self.db_password) {
+            (Some(username), Some(password)) => Ok(Credentials::User {
+                level: self.db_auth_level,
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            (None, None) => Ok(Credentials::None),
+            _ => anyhow::bail!("Database username and password must be provided together"),
+        }
     }
 
     /// Verbosity to log level
@@ -0,0 +1,153 @@
+use anyhow::Result;
+use atrium_api::{
+    record::KnownRecord,
+    types::string::{Did, RecordKey},
+};
+use futures::future::BoxFuture;
+use surrealdb::{engine::any::Any, Surreal};
+
+use super::handlers;
+
+/// Handler storing a created or updated record of a collection
+pub type CreateFn =
+    for<'a> fn(&'a Surreal<Any>, &'a Did, &'a RecordKey, KnownRecord) -> BoxFuture<'a, Result<()>>;
+
+/// How the rows of a collection table are tied to the DID owning them
+#[derive(Debug, PartialEq, Eq)]
+pub enum Owner {
+    /// The record is stored on the did row itself
+    Did,
+    /// Rows carry the owner in their author field
+    Author,
+    /// Rows are relations going out from the owner
+    Relation,
+}
+
+/// A record collection handled by the indexer
+#[derive(Debug)]
+pub struct Collection {
+    /// NSID of the collection
+    pub nsid: &'static str,
+    /// Table the records of the collection are stored in
+    pub table: &'static str,
+    /// Edge tables holding rows keyed by the id of the record
+    pub edges: &'static [&'static str],
+    /// How rows are tied to the DID owning them
+    pub owner: Owner,
+    /// Handler storing created and updated records
+    pub create: CreateFn,
+}
+
+/// Every collection the indexer handles creates, updates and deletes for
+pub const COLLECTIONS: &[Collection] = &[
+    Collection {
+        nsid: "app.bsky.actor.profile",
+        table: "did",
+        edges: &[],
+        owner: Owner::Did,
+        create: handlers::create_profile,
+    },
+    Collection {
+        nsid: "app.bsky.feed.generator",
+        table: "feed",
+        edges: &["servedby"],
+        owner: Owner::Author,
+        create: handlers::create_feed,
+    },
+    Collection {
+        nsid: "app.bsky.feed.like",
+        table: "like",
+        edges: &[],
+        owner: Owner::Relation,
+        create: handlers::create_like,
+    },
+    Collection {
+        nsid: "app.bsky.feed.post",
+        table: "post",
+        edges: &["posts", "replies", "replyto", "quotes", "thread"],
+        owner: Owner::Author,
+        create: handlers::create_post,
+    },
+    Collection {
+        nsid: "app.bsky.feed.postgate",
+        table: "lex_app_bsky_feed_postgate",
+        edges: &[],
+        owner: Owner::Author,
+        create: handlers::create_postgate,
+    },
+    Collection {
+        nsid: "app.bsky.feed.repost",
+        table: "repost",
+        edges: &[],
+        owner: Owner::Relation,
+        create: handlers::create_repost,
+    },
+    Collection {
+        nsid: "app.bsky.feed.threadgate",
+        table: "lex_app_bsky_feed_threadgate",
+        edges: &[],
+        owner: Owner::Author,
+        create: handlers::create_threadgate,
+    },
+    Collection {
+        nsid: "app.bsky.graph.block",
+        table: "block",
+        edges: &[],
+        owner: Owner::Relation,
+        create: handlers::create_block,
+    },
+    Collection {
+        nsid: "app.bsky.graph.follow",
+        table: "follow",
+        edges: &[],
+        owner: Owner::Relation,
+        create: handlers::create_follow,
+    },
+    Collection {
+        nsid: "app.bsky.graph.list",
+        table: "list",
+        edges: &[],
+        owner: Owner::Author,
+        create: handlers::create_list,
+    },
+    Collection {
+        nsid: "app.bsky.graph.listblock",
+        table: "listblock",
+        edges: &[],
+        owner: Owner::Relation,
+        create: handlers::create_listblock,
+    },
+    Collection {
+        nsid: "app.bsky.graph.listitem",
+        table: "listitem",
+        edges: &[],
+        owner: Owner::Author,
+        create: handlers::create_listitem,
+    },
+    Collection {
+        nsid: "app.bsky.graph.starterpack",
+        table: "starterpack",
+        edges: &[],
+        owner: Owner::Author,
+        create: handlers::create_starterpack,
+    },
+    Collection {
+        nsid: "app.bsky.labeler.service",
+        table: "labeler",
+        edges: &[],
+        owner: Owner::Author,
+        create: handlers::create_labeler,
+    },
+    Collection {
+        nsid: "chat.bsky.actor.declaration",
+        table: "lex_chat_bsky_actor_declaration",
+        edges: &[],
+        owner: Owner::Author,
+        create: handlers::create_chat_declaration,
+    },
+];
+
+/// Look up a handled collection by its NSID
+pub fn lookup(nsid: &str) -> Option<&'static Collection> {
+    COLLECTIONS.iter().find(|c| c.nsid == nsid)
+}
@@ -0,0 +1,229 @@
+use anyhow::Result;
+use log::info;
+use serde::Serialize;
+use surrealdb::{engine::any::Any, RecordId, Surreal};
+
+/// An edge table whose rows are counted on the records they connect
+struct Counter {
+    /// Edge table
+    edge: &'static str,
+    /// Field on the `in` record counting its outgoing edges
+    outgoing: Option<&'static str>,
+    /// Field on the `out` record counting its incoming edges
+    incoming: Option<&'static str>,
+    /// Tables carrying the counter fields
+    tables: &'static [&'static str],
+}
+
+/// All counted edge tables
+const COUNTERS: &[Counter] = &[
+    Counter {
+        edge: "like",
+        outgoing: None,
+        incoming: Some("likeCount"),
+        tables: &["post", "feed", "list", "starterpack", "labeler"],
+    },
+    Counter {
+        edge: "repost",
+        outgoing: None,
+        incoming: Some("repostCount"),
+        tables: &["post"],
+    },
+    Counter {
+        edge: "replyto",
+        outgoing: None,
+        incoming: Some("replyCount"),
+        tables: &["post"],
+    },
+    Counter {
+        edge: "quotes",
+        outgoing: None,
+        incoming: Some("quoteCount"),
+        tables: &["post"],
+    },
+    Counter {
+        edge: "follow",
+        outgoing: Some("followingCount"),
+        incoming: Some("followerCount"),
+        tables: &["did"],
+    },
+];
+
+/// Look up the counter of an edge table
+fn lookup(edge: &str) -> Option<&'static Counter> {
+    COUNTERS.iter().find(|c| c.edge == edge)
+}
+
+/// Relate two records, bumping their counters only if the edge is new
+pub async fn relate(
+    db: &Surreal<Any>,
+    from: &RecordId,
+    edge: &str,
+    to: &RecordId,
+    id: &str,
+    fields: &str,
+) -> Result<()> {
+    let edge_id = RecordId::from_table_key(edge, id);
+
+    let mut statements = if fields.is_empty() {
+        format!("RELATE {}->{}->{} SET id = '{}';", from, edge, to, id)
+    } else {
+        format!(
+            "RELATE {}->{}->{} SET id = '{}', {};",
+            from, edge, to, id, fields
+        )
+    };
+    if let Some(counter) = lookup(edge) {
+        if let Some(field) = counter
+            .outgoing
+            .filter(|_| counter.tables.contains(&from.table()))
+        {
+            statements.push_str(&increment(from, field));
+        }
+        if let Some(field) = counter
+            .incoming
+            .filter(|_| counter.tables.contains(&to.table()))
+        {
+            statements.push_str(&increment(to, field));
+        }
+    }
+
+    // duplicate events must not bump the counters twice
+    let query = format!(
+        "BEGIN TRANSACTION; IF (SELECT VALUE id FROM ONLY {}) == NONE {{ {} }}; COMMIT TRANSACTION;",
+        edge_id, statements
+    );
+    let _ = db.query(query).await?;
+
+    Ok(())
+}
+
+/// Build the statement incrementing a counter field of a record
+fn increment(record: &RecordId, field: &str) -> String {
+    if record.table() == "did" {
+        // follows may arrive before the profile, so create the row if needed
+        format!(
+            "UPSERT {} SET {} += 1, seenAt = seenAt ?? time::now();",
+            record, field
+        )
+    } else {
+        // records that don't exist yet are counted once they are created
+        format!("UPDATE {} SET {} += 1;", record, field)
+    }
+}
+
+/// Delete a record, decrementing the counters of the records it connected
+pub async fn delete_record(db: &Surreal<Any>, table: &str, key: &str) -> Result<()> {
+    let Some(counter) = lookup(table) else {
+        return super::delete_record(db, table, key).await;
+    };
+
+    let mut decrements = String::new();
+    if let Some(field) = counter.outgoing {
+        decrements.push_str(&format!(
+            "UPDATE $edge.in SET {0} -= 1 WHERE {0} > 0;",
+            field
+        ));
+    }
+    if let Some(field) = counter.incoming {
+        decrements.push_str(&format!(
+            "UPDATE $edge.out SET {0} -= 1 WHERE {0} > 0;",
+            field
+        ));
+    }
+
+    // only decrement if the edge actually existed and was counted, orphaned
+    // edges aren't counted
+    let edge_id = RecordId::from_table_key(table, key);
+    let query = format!(
+        "BEGIN TRANSACTION; LET $edge = (SELECT in, out, orphaned FROM ONLY {}); IF $edge != NONE {{ DELETE {}; IF $edge.orphaned != true {{ {} }}; }}; COMMIT TRANSACTION;",
+        edge_id, edge_id, decrements
+    );
+    let _ = db.query(query).await?;
+
+    Ok(())
+}
+
+/// Build the assignments recomputing all counters of a table from its edges
+fn assignments(table: &str) -> String {
+    COUNTERS
+        .iter()
+        .filter(|c| c.tables.contains(&table))
+        .flat_map(|c| {
+            c.outgoing
+                .map(|f| format!("{} = count(->{}[WHERE orphaned != true])", f, c.edge))
+                .into_iter()
+                .chain(
+                    c.incoming
+                        .map(|f| format!("{} = count(<-{}[WHERE orphaned != true])", f, c.edge)),
+                )
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Create or update a record without touching its counters
+///
+/// Edges created before the record itself weren't counted, so the counters of
+/// a new record are computed from its edges once.
+pub async fn upsert<T>(db: &Surreal<Any>, record: &RecordId, content: T) -> Result<()>
+where
+    T: Serialize + 'static,
+{
+    let assignments = assignments(record.table());
+    let recount = if assignments.is_empty() {
+        String::new()
+    } else {
+        format!("IF $new {{ UPDATE $record SET {}; }};", assignments)
+    };
+
+    let query = format!(
+        "BEGIN TRANSACTION; LET $new = (SELECT VALUE id FROM ONLY $record) == NONE; UPSERT $record MERGE $content; {} COMMIT TRANSACTION;",
+        recount
+    );
+    db.query(query)
+        .bind(("record", record.clone()))
+        .bind(("content", content))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+/// Recompute the counters of every record from its edges, in batches of
+/// records ordered by id
+pub async fn repair(db: &Surreal<Any>) -> Result<()> {
+    let mut tables: Vec<&str> = COUNTERS
+        .iter()
+        .flat_map(|c| c.tables.iter().copied())
+        .collect();
+    tables.sort();
+    tables.dedup();
+
+    for table in tables {
+        info!(target: "indexer", "Recomputing counters of table {}", table);
+        let query = format!("UPDATE $ids SET {} RETURN NONE;", assignments(table));
+        super::update_in_batches(db, table, &query).await?;
+    }
+
+    info!(target: "indexer", "All counters recomputed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recount_skips_orphaned_edges() {
+        assert_eq!(
+            assignments("did"),
+            "followingCount = count(->follow[WHERE orphaned != true]), followerCount = count(<-follow[WHERE orphaned != true])"
+        );
+        assert_eq!(
+            assignments("post"),
+            "likeCount = count(<-like[WHERE orphaned != true]), repostCount = count(<-repost[WHERE orphaned != true]), replyCount = count(<-replyto[WHERE orphaned != true]), quoteCount = count(<-quotes[WHERE orphaned != true])"
+        );
+        assert!(assignments("cursor").is_empty());
+    }
+}
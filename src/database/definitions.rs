@@ -1,8 +1,12 @@
+use std::collections::BTreeMap;
+
 use anyhow::Context;
 use log::debug;
 use serde::{Deserialize, Serialize};
 use surrealdb::{engine::any::Any, Datetime, RecordId, Surreal};
 
+use super::collections::COLLECTIONS;
+
 /// Database struct for a bluesky profile
 #[derive(Debug, Serialize)]
 #[allow(dead_code)]
@@ -25,6 +29,21 @@ pub struct BskyProfile {
     pub extra_data: Option<String>,
 }
 
+impl BskyProfile {
+    /// Fields of the did table that are owned by the profile record
+    pub const FIELDS: &'static [&'static str] = &[
+        "displayName",
+        "description",
+        "avatar",
+        "banner",
+        "createdAt",
+        "joinedViaStarterPack",
+        "labels",
+        "pinnedPost",
+        "extraData",
+    ];
+}
+
 /// Database struct for a record
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
@@ -118,6 +137,14 @@ pub struct BskyPostMediaAspectRatio {
     pub height: u64,
 }
 
+/// Database struct for the mentions, links and tags of richtext facets
+#[derive(Debug, Default, Serialize)]
+pub struct BskyFacets {
+    pub mentions: Vec<RecordId>,
+    pub links: Vec<String>,
+    pub tags: Vec<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct BskyFeed {
     pub uri: String,
@@ -127,184 +154,184 @@ pub struct BskyFeed {
     #[serde(rename = "displayName")]
     pub display_name: String,
     pub description: Option<String>,
+    #[serde(rename = "descriptionFacets")]
+    pub description_facets: Option<BskyFacets>,
+    #[serde(rename = "acceptsInteractions")]
+    pub accepts_interactions: Option<bool>,
+    #[serde(rename = "contentMode")]
+    pub content_mode: Option<String>,
     pub avatar: Option<RecordId>,
     #[serde(rename = "createdAt")]
     pub created_at: Datetime,
+    pub labels: Option<Vec<String>>,
     #[serde(rename = "extraData")]
     pub extra_data: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct BskyList {
+    pub author: RecordId,
     pub name: String,
     pub purpose: String,
     #[serde(rename = "createdAt")]
     pub created_at: Datetime,
     pub description: Option<String>,
+    #[serde(rename = "descriptionFacets")]
+    pub description_facets: Option<BskyFacets>,
     pub avatar: Option<RecordId>,
     pub labels: Option<Vec<String>>,
     #[serde(rename = "extraData")]
     pub extra_data: Option<String>,
 }
 
-/// Initialize the database with the necessary definitions
-pub async fn init(db: &Surreal<Any>) -> anyhow::Result<()> {
+/// Database struct for a bluesky starterpack
+#[derive(Debug, Serialize)]
+pub struct BskyStarterpack {
+    pub author: RecordId,
+    pub name: String,
+    pub description: Option<String>,
+    pub list: RecordId,
+    pub feeds: Option<Vec<RecordId>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Datetime,
+    #[serde(rename = "extraData")]
+    pub extra_data: Option<String>,
+}
+
+/// Database struct for a bluesky labeler service
+#[derive(Debug, Serialize)]
+pub struct BskyLabeler {
+    pub author: RecordId,
+    #[serde(rename = "labelValues")]
+    pub label_values: Vec<String>,
+    #[serde(rename = "labelValueDefinitions")]
+    pub label_value_definitions: Option<Vec<BskyLabelValueDefinition>>,
+    pub labels: Option<Vec<String>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Datetime,
+    #[serde(rename = "extraData")]
+    pub extra_data: Option<String>,
+}
+
+/// Database struct for a custom label value defined by a labeler
+#[derive(Debug, Serialize)]
+pub struct BskyLabelValueDefinition {
+    pub identifier: String,
+    pub severity: String,
+    pub blurs: String,
+    #[serde(rename = "defaultSetting")]
+    pub default_setting: Option<String>,
+    #[serde(rename = "adultOnly")]
+    pub adult_only: Option<bool>,
+    pub locales: Vec<BskyLabelValueDefinitionStrings>,
+}
+
+/// Database struct for the localized strings of a label value definition
+#[derive(Debug, Serialize)]
+pub struct BskyLabelValueDefinitionStrings {
+    pub lang: String,
+    pub name: String,
+    pub description: String,
+}
+
+/// Database struct for a bluesky threadgate
+#[derive(Debug, Serialize)]
+pub struct BskyThreadgate {
+    pub author: RecordId,
+    pub post: RecordId,
+    /// Reply rules, `None` allows everyone and an empty list nobody to reply
+    pub allow: Option<Vec<String>>,
+    #[serde(rename = "allowLists")]
+    pub allow_lists: Option<Vec<RecordId>>,
+    #[serde(rename = "hiddenReplies")]
+    pub hidden_replies: Option<Vec<RecordId>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Datetime,
+    #[serde(rename = "extraData")]
+    pub extra_data: Option<String>,
+}
+
+/// Database struct for a bluesky postgate
+#[derive(Debug, Serialize)]
+pub struct BskyPostgate {
+    pub author: RecordId,
+    pub post: RecordId,
+    #[serde(rename = "detachedEmbeddings")]
+    pub detached_embeddings: Option<Vec<RecordId>>,
+    #[serde(rename = "embeddingDisabled")]
+    pub embedding_disabled: bool,
+    #[serde(rename = "createdAt")]
+    pub created_at: Datetime,
+    #[serde(rename = "extraData")]
+    pub extra_data: Option<String>,
+}
+
+/// Database struct for a chat actor declaration
+#[derive(Debug, Serialize)]
+pub struct ChatDeclaration {
+    pub author: RecordId,
+    #[serde(rename = "allowIncoming")]
+    pub allow_incoming: String,
+    #[serde(rename = "extraData")]
+    pub extra_data: Option<String>,
+}
+
+/// Tables written by the indexer that don't belong to a collection
+const AUXILIARY_TABLES: &[&str] = &[
+    "includes",
+    "jetstream_identity",
+    "jetstream_account",
+    "cursor",
+    "li_did",
+    "schema_version",
+    "did_cache",
+    "backfill_did",
+    "seed_cursor",
+    "repo_rev",
+    "managed_index",
+];
+
+/// Ensure every table the indexer writes to is defined in the database
+pub async fn verify_tables(db: &Surreal<Any>) -> anyhow::Result<()> {
+    let mut res = db.query("INFO FOR DB;").await?;
+    let defined: Option<BTreeMap<String, String>> = res.take((0, "tables"))?;
+    let defined = defined.unwrap_or_default();
+
+    let missing: Vec<&str> = COLLECTIONS
+        .iter()
+        .flat_map(|c| std::iter::once(c.table).chain(c.edges.iter().copied()))
+        .chain(AUXILIARY_TABLES.iter().copied())
+        .filter(|table| !defined.contains_key(*table))
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "Refusing to write to undefined tables: {}",
+            missing.join(", ")
+        );
+    }
+
+    debug!(target: "indexer", "All {} tables are defined", defined.len());
+    Ok(())
+}
+
+/// Initialize the namespace and database used by the indexer
+pub async fn init(db: &Surreal<Any>, namespace: &str, database: &str) -> anyhow::Result<()> {
+    // users without the permission to define the namespace or database can
+    // still use them if they exist already, so errors are not checked here
+
     // define the namespace
-    debug!(target: "indexer", "Defining namespace");
-    db.query("DEFINE NAMESPACE atp;")
+    debug!(target: "indexer", "Defining namespace {}", namespace);
+    db.query(format!("DEFINE NAMESPACE IF NOT EXISTS `{}`;", namespace))
         .await
-        .context("Failed to define namespace atp")?;
-    db.use_ns("atp").await?;
+        .with_context(|| format!("Failed to define namespace {}", namespace))?;
+    db.use_ns(namespace).await?;
 
     // define the database
-    debug!(target: "indexer", "Defining database");
-    db.query("DEFINE DATABASE atp;")
+    debug!(target: "indexer", "Defining database {}", database);
+    db.query(format!("DEFINE DATABASE IF NOT EXISTS `{}`;", database))
         .await
-        .context("Failed to define database atp")?;
-    db.use_ns("atp").use_db("atp").await?;
-
-    // TODO Add all types
-    db.query(
-        "
-DEFINE TABLE did SCHEMAFULL;
-DEFINE FIELD handle ON TABLE did TYPE option<string>;
-DEFINE FIELD displayName ON TABLE did TYPE option<string>;
-DEFINE FIELD description ON TABLE did TYPE option<string>;
-DEFINE FIELD avatar ON TABLE did TYPE option<record<blob>>;
-DEFINE FIELD banner ON TABLE did TYPE option<record<blob>>;
-DEFINE FIELD labels ON TABLE did TYPE option<array<string>>;
-DEFINE FIELD joinedViaStarterPack ON TABLE did TYPE option<record<starterpack>>;
-DEFINE FIELD pinnedPost ON TABLE did TYPE option<record<post>>;
-DEFINE FIELD createdAt ON TABLE did TYPE option<datetime>;
-DEFINE FIELD seenAt ON TABLE did TYPE datetime;
-DEFINE FIELD extraData ON TABLE did TYPE option<string>;
-
-DEFINE TABLE post SCHEMAFULL;
-DEFINE FIELD author ON TABLE post TYPE record<did>;
-DEFINE FIELD bridgyOriginalUrl ON TABLE post TYPE option<string>;
-DEFINE FIELD createdAt ON TABLE post TYPE datetime;
-DEFINE FIELD images ON TABLE post TYPE option<array>;
-DEFINE FIELD images.* ON TABLE post TYPE object;
-DEFINE FIELD images.*.alt ON TABLE post TYPE string;
-DEFINE FIELD images.*.blob ON TABLE post TYPE record<blob>;
-DEFINE FIELD images.*.aspectRatio ON TABLE post TYPE option<object>;
-DEFINE FIELD images.*.aspectRatio.height ON TABLE post TYPE option<int>;
-DEFINE FIELD images.*.aspectRatio.width ON TABLE post TYPE option<int>;
-DEFINE FIELD labels ON TABLE post TYPE option<array<string>>;
-DEFINE FIELD langs ON TABLE post TYPE option<array<string>>;
-DEFINE FIELD links ON TABLE post TYPE option<array<string>>;
-DEFINE FIELD mentions ON TABLE post TYPE option<array<record<did>>>;
-DEFINE FIELD parent ON TABLE post TYPE option<record<post>>;
-DEFINE FIELD record ON TABLE post TYPE option<record>;
-DEFINE FIELD root ON TABLE post TYPE option<record<post>>;
-DEFINE FIELD tags ON TABLE post TYPE option<array<string>>;
-DEFINE FIELD text ON TABLE post TYPE string;
-DEFINE FIELD via ON TABLE post TYPE option<string>;
-DEFINE FIELD video ON TABLE post TYPE option<object>;
-DEFINE FIELD video.alt ON TABLE post TYPE option<string>;
-DEFINE FIELD video.aspectRatio ON TABLE post TYPE option<object>;
-DEFINE FIELD video.aspectRatio.height ON TABLE post TYPE option<int>;
-DEFINE FIELD video.aspectRatio.width ON TABLE post TYPE option<int>;
-DEFINE FIELD video.blob ON TABLE post TYPE option<object>;
-DEFINE FIELD video.blob.cid ON TABLE post TYPE option<string>;
-DEFINE FIELD video.blob.mediaType ON TABLE post TYPE option<string>;
-DEFINE FIELD video.blob.size ON TABLE post TYPE option<int>;
-DEFINE FIELD video.captions ON TABLE post TYPE option<array<object>>;
-DEFINE FIELD extraData ON TABLE post TYPE option<string>;
-
-DEFINE TABLE feed SCHEMAFULL;
-DEFINE FIELD uri ON TABLE feed TYPE string;
-DEFINE FIELD author ON TABLE feed TYPE record<did>;
-DEFINE FIELD rkey ON TABLE feed TYPE string;
-DEFINE FIELD did ON TABLE feed TYPE string;
-DEFINE FIELD displayName ON TABLE feed TYPE string;
-DEFINE FIELD description ON TABLE feed TYPE option<string>;
-DEFINE FIELD avatar ON TABLE feed TYPE option<record<blob>>;
-DEFINE FIELD createdAt ON TABLE feed TYPE datetime;
-DEFINE FIELD extraData ON TABLE feed TYPE option<string>;
-
-DEFINE TABLE list SCHEMAFULL;
-DEFINE FIELD name ON TABLE list TYPE string;
-DEFINE FIELD purpose ON TABLE list TYPE string;
-DEFINE FIELD createdAt ON TABLE list TYPE datetime;
-DEFINE FIELD description ON TABLE list TYPE option<string>;
-DEFINE FIELD avatar ON TABLE list TYPE option<record<blob>>;
-DEFINE FIELD labels ON TABLE list TYPE option<array<string>>;
-DEFINE FIELD extraData ON TABLE list TYPE option<string>;
-
-
-DEFINE TABLE follow SCHEMAFULL TYPE RELATION FROM did TO did;
-DEFINE FIELD createdAt ON TABLE follow TYPE datetime;
-
-DEFINE TABLE block SCHEMAFULL TYPE RELATION FROM did TO did;
-DEFINE FIELD createdAt ON TABLE block TYPE datetime;
-
-DEFINE TABLE like SCHEMAFULL TYPE RELATION FROM did TO post|feed|list|starterpack|labeler;
-DEFINE FIELD createdAt ON TABLE like TYPE datetime;
-
-DEFINE TABLE listitem SCHEMAFULL TYPE RELATION FROM list TO did;
-DEFINE FIELD createdAt ON TABLE listitem TYPE datetime;
-
-DEFINE TABLE posts SCHEMAFULL TYPE RELATION FROM did TO post;
-DEFINE TABLE replies SCHEMAFULL TYPE RELATION FROM did TO post;
-
-DEFINE TABLE quotes SCHEMAFULL TYPE RELATION FROM post TO post;
-
-DEFINE TABLE replyto SCHEMAFULL TYPE RELATION FROM post TO post;
-
-DEFINE TABLE repost SCHEMAFULL TYPE RELATION FROM did TO post;
-DEFINE FIELD createdAt ON TABLE repost TYPE datetime;
-
-DEFINE TABLE like_count_view TYPE NORMAL AS
-SELECT
-  count() AS c,
-  ->out.id AS out
-  FROM like
-  GROUP BY out
-;
-
-DEFINE TABLE repost_count_view TYPE NORMAL AS
-SELECT
-  count() AS c,
-  ->out.id AS out
-  FROM repost
-  GROUP BY out
-;
-
-DEFINE TABLE reply_count_view TYPE NORMAL AS
-SELECT
-  count() AS c,
-  ->out.id AS out
-  FROM replyto
-  GROUP BY out
-;
-
-DEFINE TABLE quote_count_view TYPE NORMAL AS
-SELECT
-  count() AS c,
-  ->out.id AS out
-  FROM quotes
-  GROUP BY out
-;
-
-DEFINE TABLE following_count_view TYPE NORMAL AS
-SELECT
-  count() AS c,
-  ->in.id AS in
-  FROM follow
-  GROUP BY in
-;
-
-DEFINE TABLE follower_count_view TYPE NORMAL AS
-SELECT
-  count() AS c,
-  ->out.id AS out
-  FROM follow
-  GROUP BY out
-;
-        ", // record<one | two>
-    )
-    .await?;
+        .with_context(|| format!("Failed to define database {}", database))?;
+    db.use_ns(namespace).use_db(database).await?;
 
     Ok(())
 }
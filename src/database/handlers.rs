@@ -1,7 +1,9 @@
 use anyhow::Result;
-use atrium_api::app::bsky::richtext::facet::MainFeaturesItem;
 use atrium_api::{
-    app::bsky::embed::video,
+    app::bsky::{
+        embed::video,
+        feed::{postgate::RecordEmbeddingRulesItem, threadgate::RecordAllowItem},
+    },
     record::KnownRecord,
     types::{
         string::{Did, RecordKey},
@@ -9,22 +11,56 @@ use atrium_api::{
     },
 };
 use chrono::Utc;
+use futures::future::BoxFuture;
 use log::warn;
+use std::sync::OnceLock;
 use surrealdb::{engine::any::Any, RecordId, Surreal};
 
-use crate::websocket::events::{Commit, Kind};
+use crate::{
+    config::PostDeleteCascade,
+    websocket::events::{Commit, Kind},
+};
 
 use super::{
+    collections, counters,
     definitions::{
-        BskyFeed, BskyList, BskyPost, BskyPostImage, BskyPostMediaAspectRatio, BskyPostVideo,
-        BskyPostVideoBlob, BskyProfile, JetstreamAccountEvent, JetstreamIdentityEvent, Record,
+        BskyFeed, BskyLabelValueDefinition, BskyLabelValueDefinitionStrings, BskyLabeler, BskyList,
+        BskyPost, BskyPostImage, BskyPostMediaAspectRatio, BskyPostVideo, BskyPostVideoBlob,
+        BskyPostgate, BskyProfile, BskyStarterpack, BskyThreadgate, ChatDeclaration,
+        JetstreamAccountEvent, JetstreamIdentityEvent, Record,
     },
-    delete_record,
-    utils::{self, at_uri_to_record_id, blob_ref_to_record_id, did_to_key},
+    repo_indexer,
+    utils::{self, at_uri_to_record_id, blob_ref_to_record_id},
 };
 
+/// Edge tables pointing at a post that are subject to the delete cascade
+const POST_REFERENCES: &[&str] = &["like", "repost", "quotes", "replyto", "thread"];
+
+static POST_DELETE_CASCADE: OnceLock<PostDeleteCascade> = OnceLock::new();
+static TRACK_REPO_REV: OnceLock<bool> = OnceLock::new();
+
+/// Set the cascade policy applied when a post is deleted
+pub fn set_post_delete_cascade(policy: PostDeleteCascade) {
+    let _ = POST_DELETE_CASCADE.set(policy);
+}
+
+/// Set whether the latest revision of every repo seen live is recorded
+pub fn set_track_repo_rev(enabled: bool) {
+    let _ = TRACK_REPO_REV.set(enabled);
+}
+
 /// Handle a new websocket event on the database
 pub async fn handle_event(db: &Surreal<Any>, event: Kind) -> Result<()> {
+    // events of repos being backfilled are applied once the snapshot is written
+    let Some(event) = repo_indexer::buffer::intercept(event) else {
+        return Ok(());
+    };
+
+    apply_event(db, event).await
+}
+
+/// Write a websocket event to the database
+pub async fn apply_event(db: &Surreal<Any>, event: Kind) -> Result<()> {
     // Handle event types
     match event {
         Kind::CommitEvent {
@@ -34,6 +70,9 @@ pub async fn handle_event(db: &Surreal<Any>, event: Kind) -> Result<()> {
         } => {
             // Handle types of commits
             let did_key = utils::did_to_key(did.as_str())?;
+            let rev = match &commit {
+                Commit::CreateOrUpdate { rev, .. } | Commit::Delete { rev, .. } => rev.clone(),
+            };
             match commit {
                 Commit::CreateOrUpdate {
                     rev,
@@ -41,18 +80,27 @@ pub async fn handle_event(db: &Surreal<Any>, event: Kind) -> Result<()> {
                     rkey,
                     record,
                     cid,
-                } => {
-                    on_commit_event_createorupdate(db, did, did_key, collection, rkey, record)
-                        .await?
-                }
+                } => on_commit_event_createorupdate(db, did, collection, rkey, record).await?,
                 Commit::Delete {
                     rev,
                     collection,
                     rkey,
                 } => {
-                    on_commit_event_delete(db, did, time_us, did_key, rev, collection, rkey).await?
+                    on_commit_event_delete(db, did, time_us, did_key.clone(), rev, collection, rkey)
+                        .await?
                 }
             }
+
+            // remember the latest revision of the repo, so lagging backfills can be found
+            if TRACK_REPO_REV.get().copied().unwrap_or_default() {
+                db.query(format!(
+                    "UPSERT repo_rev:{} SET rev = IF rev = NONE OR rev < $rev THEN $rev ELSE rev END, seenAt = time::now();",
+                    did_key
+                ))
+                .bind(("rev", rev))
+                .await?
+                .check()?;
+            }
         }
         Kind::IdentityEvent {
             did,
@@ -95,391 +143,814 @@ pub async fn handle_event(db: &Surreal<Any>, event: Kind) -> Result<()> {
 pub async fn on_commit_event_createorupdate(
     db: &Surreal<Any>,
     did: Did,
-    did_key: String,
     collection: String,
     rkey: RecordKey,
     record: KnownRecord,
 ) -> Result<()> {
     utils::ensure_valid_rkey(rkey.to_string())?;
-    match record {
-        KnownRecord::AppBskyActorProfile(d) => {
-            // NOTE: using .ok() here isn't optimal, incorrect data should
-            // probably not be entered into the database at all, but for now
-            // we'll just ignore it.
-            let profile = BskyProfile {
-                display_name: d.display_name.clone(),
-                description: d.description.clone(),
-                avatar: None, // TODO Implement
-                banner: None, // TODO Implement
-                created_at: d
-                    .created_at
-                    .as_ref()
-                    .and_then(|dt| utils::extract_dt(dt).ok()),
-                seen_at: Utc::now().into(),
-                joined_via_starter_pack: d
-                    .joined_via_starter_pack
-                    .as_ref()
-                    .and_then(|d| utils::strong_ref_to_record_id(d).ok()),
-                // TODO if strong_ref_to_record_id fails, it should return an error result instead of being empty
-                pinned_post: d
-                    .pinned_post
-                    .as_ref()
-                    .and_then(|d| utils::strong_ref_to_record_id(d).ok()),
-                labels: d
-                    .labels
-                    .as_ref()
-                    .and_then(|d| utils::extract_self_labels_profile(d)),
-                extra_data: process_extra_data(&d.extra_data)?,
-            };
-            // TODO this should be a db.upsert(...).merge(...)
-            let _: Option<Record> = db.upsert(("did", did_key)).content(profile).await?;
-        }
-        KnownRecord::AppBskyGraphFollow(d) => {
-            // TODO ensure_valid_rkey_strict(rkey.as_str())?;
-            let from = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), from);
-            let to = utils::did_to_key(d.subject.as_str())?;
-            let created_at = utils::extract_dt(&d.created_at)?;
+    let Some(c) = collections::lookup(&collection) else {
+        warn!(target: "indexer", "ignored create_or_update {} {} {}",
+            did.as_str(), collection, rkey.as_str());
+        return Ok(());
+    };
 
-            let query = format!(
-                "RELATE did:{}->follow->did:{} SET id = '{}', createdAt = {};",
-                from, to, id, created_at
+    (c.create)(db, &did, &rkey, record).await
+}
+
+/// Store a profile on the did row of its owner
+pub(super) fn create_profile<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyActorProfile(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.actor.profile",
+                rkey.as_str()
             );
+        };
+        let did_key = utils::did_to_key(did.as_str())?;
 
-            let _ = db.query(query).await?;
-        }
-        KnownRecord::AppBskyFeedLike(d) => {
-            // TODO ensure_valid_rkey_strict(rkey.as_str())?;
-            let from = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), from);
-            let to = utils::at_uri_to_record_id(&d.subject.uri)?;
-            let created_at = utils::extract_dt(&d.created_at)?;
+        // NOTE: using .ok() here isn't optimal, incorrect data should
+        // probably not be entered into the database at all, but for now
+        // we'll just ignore it.
+        let profile = BskyProfile {
+            display_name: d.display_name.clone(),
+            description: d.description.clone(),
+            avatar: None, // TODO Implement
+            banner: None, // TODO Implement
+            created_at: d
+                .created_at
+                .as_ref()
+                .and_then(|dt| utils::extract_dt(dt).ok()),
+            seen_at: Utc::now().into(),
+            joined_via_starter_pack: d
+                .joined_via_starter_pack
+                .as_ref()
+                .and_then(|d| utils::strong_ref_to_record_id(d).ok()),
+            // TODO if strong_ref_to_record_id fails, it should return an error result instead of being empty
+            pinned_post: d
+                .pinned_post
+                .as_ref()
+                .and_then(|d| utils::strong_ref_to_record_id(d).ok()),
+            labels: d
+                .labels
+                .as_ref()
+                .and_then(|d| utils::extract_self_labels(d)),
+            extra_data: process_extra_data(&d.extra_data)?,
+        };
+        // merge instead of replacing the row, so fields not owned by the
+        // profile record (handle, counters, ...) are left untouched. unset
+        // profile fields serialize to NONE and are removed by the merge.
+        let _: Option<Record> = db.upsert(("did", did_key)).merge(profile).await?;
 
-            let query = format!(
-                "RELATE did:{}->like->{} SET id = '{}', createdAt = {};",
-                from, to, id, created_at
+        Ok(())
+    })
+}
+
+/// Store a follow
+pub(super) fn create_follow<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyGraphFollow(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.graph.follow",
+                rkey.as_str()
             );
+        };
+        // TODO ensure_valid_rkey_strict(rkey.as_str())?;
+        let from = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), from);
+        let to = utils::did_to_key(d.subject.as_str())?;
+        let created_at = utils::extract_dt(&d.created_at)?;
 
-            let _ = db.query(query).await?;
-        }
-        KnownRecord::AppBskyFeedRepost(d) => {
-            // TODO ensure_valid_rkey_strict(rkey.as_str())?;
-            let from = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), from);
-            let to = utils::at_uri_to_record_id(&d.subject.uri)?;
-            let created_at = utils::extract_dt(&d.created_at)?;
+        counters::relate(
+            db,
+            &RecordId::from_table_key("did", from),
+            "follow",
+            &RecordId::from_table_key("did", to),
+            &id,
+            &format!("createdAt = {}", created_at),
+        )
+        .await?;
 
-            let query = format!(
-                "RELATE did:{}->repost->{} SET id = '{}', createdAt = {};",
-                from, to, id, created_at
+        Ok(())
+    })
+}
+
+/// Store a like
+pub(super) fn create_like<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyFeedLike(d) = record else {
+            anyhow::bail!("Record {} is not of type app.bsky.feed.like", rkey.as_str());
+        };
+        // TODO ensure_valid_rkey_strict(rkey.as_str())?;
+        let from = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), from);
+        let to = utils::at_uri_to_record_id(&d.subject.uri)?;
+        let created_at = utils::extract_dt(&d.created_at)?;
+
+        counters::relate(
+            db,
+            &RecordId::from_table_key("did", from),
+            "like",
+            &to,
+            &id,
+            &format!("createdAt = {}", created_at),
+        )
+        .await?;
+
+        Ok(())
+    })
+}
+
+/// Store a repost
+pub(super) fn create_repost<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyFeedRepost(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.feed.repost",
+                rkey.as_str()
             );
+        };
+        // TODO ensure_valid_rkey_strict(rkey.as_str())?;
+        let from = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), from);
+        let to = utils::at_uri_to_record_id(&d.subject.uri)?;
+        let created_at = utils::extract_dt(&d.created_at)?;
 
-            let _ = db.query(query).await?;
-        }
-        KnownRecord::AppBskyGraphBlock(d) => {
-            // TODO ensure_valid_rkey_strict(rkey.as_str())?;
-            let from = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), from);
-            let to = utils::did_to_key(d.subject.as_str())?;
-            let created_at = utils::extract_dt(&d.created_at)?;
+        counters::relate(
+            db,
+            &RecordId::from_table_key("did", from),
+            "repost",
+            &to,
+            &id,
+            &format!("createdAt = {}", created_at),
+        )
+        .await?;
 
-            let query = format!(
-                "RELATE did:{}->block->did:{} SET id = '{}', createdAt = {};",
-                from, to, id, created_at
+        Ok(())
+    })
+}
+
+/// Store a block
+pub(super) fn create_block<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyGraphBlock(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.graph.block",
+                rkey.as_str()
             );
+        };
+        // TODO ensure_valid_rkey_strict(rkey.as_str())?;
+        let from = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), from);
+        let to = utils::did_to_key(d.subject.as_str())?;
+        let created_at = utils::extract_dt(&d.created_at)?;
 
-            let _ = db.query(query).await?;
-        }
-        KnownRecord::AppBskyGraphListblock(d) => {
-            // TODO ensure_valid_rkey_strict(rkey.as_str())?;
-            let from = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), from);
-            let to = utils::at_uri_to_record_id(&d.subject)?;
-            let created_at = utils::extract_dt(&d.created_at)?;
+        let query = format!(
+            "RELATE did:{}->block->did:{} SET id = '{}', createdAt = {};",
+            from, to, id, created_at
+        );
 
-            let query = format!(
-                "RELATE did:{}->listblock->{} SET id = '{}', createdAt = {};",
-                from, to, id, created_at
+        let _ = db.query(query).await?;
+
+        Ok(())
+    })
+}
+
+/// Store a list block
+pub(super) fn create_listblock<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyGraphListblock(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.graph.listblock",
+                rkey.as_str()
             );
+        };
+        // TODO ensure_valid_rkey_strict(rkey.as_str())?;
+        let from = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), from);
+        let to = utils::at_uri_to_record_id(&d.subject)?;
+        let created_at = utils::extract_dt(&d.created_at)?;
 
-            let _ = db.query(query).await?;
-        }
-        KnownRecord::AppBskyGraphListitem(d) => {
-            // TODO ensure_valid_rkey_strict(rkey.as_str())?;
-            let from = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), from);
+        let query = format!(
+            "RELATE did:{}->listblock->{} SET id = '{}', createdAt = {};",
+            from, to, id, created_at
+        );
 
-            let from = utils::at_uri_to_record_id(&d.list)?;
-            let to = utils::did_to_key(&d.subject)?;
-            let created_at = utils::extract_dt(&d.created_at)?;
+        let _ = db.query(query).await?;
 
-            let query = format!(
-                "RELATE {}->listitem->did:{} SET id = '{}', createdAt = {};",
-                from, to, id, created_at
+        Ok(())
+    })
+}
+
+/// Store a list item
+pub(super) fn create_listitem<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyGraphListitem(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.graph.listitem",
+                rkey.as_str()
             );
+        };
+        // TODO ensure_valid_rkey_strict(rkey.as_str())?;
+        let author = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), author);
 
-            let _ = db.query(query).await?;
-        }
-        KnownRecord::AppBskyFeedGenerator(d) => {
-            let did_key = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), did_key);
-            let feed = BskyFeed {
-                author: RecordId::from_table_key("did", did_key),
-                avatar: None, // TODO implement
-                created_at: utils::extract_dt(&d.created_at)?,
-                description: d.description.clone(),
-                did: d.did.to_string(),
-                display_name: d.display_name.clone(),
-                rkey: rkey.to_string(),
-                uri: format!(
-                    "at://{}/app.bsky.feed.generator/{}",
-                    did.as_str(),
-                    rkey.as_str()
-                ),
-                extra_data: process_extra_data(&d.extra_data)?,
-            };
-            let _: Option<Record> = db.upsert(("feed", id)).content(feed).await?;
-        }
-        KnownRecord::AppBskyGraphList(d) => {
-            let did_key = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), did_key);
-
-            let list = BskyList {
-                name: d.name.clone(),
-                avatar: None, // TODO implement
-                created_at: utils::extract_dt(&d.created_at)?,
-                description: d.description.clone(),
-                labels: d
-                    .labels
-                    .as_ref()
-                    .and_then(|d| utils::extract_self_labels_list(d)),
-                purpose: d.purpose.clone(),
-                extra_data: process_extra_data(&d.extra_data)?,
-            };
-            let _: Option<Record> = db.upsert(("list", id)).content(list).await?;
-        }
-        KnownRecord::AppBskyFeedThreadgate(d) => {
-            let did_key = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), did_key);
-            let _: Option<Record> = db
-                .upsert(("lex_app_bsky_feed_threadgate", id))
-                .content(d)
-                .await?;
-        }
-        KnownRecord::AppBskyGraphStarterpack(d) => {
-            let did_key = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), did_key);
-            let _: Option<Record> = db
-                .upsert(("lex_app_bsky_graph_starterpack", id))
-                .content(d)
-                .await?;
-        }
-        KnownRecord::AppBskyFeedPostgate(d) => {
-            let did_key = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), did_key);
-            let _: Option<Record> = db
-                .upsert(("lex_app_bsky_feed_postgate", id))
-                .content(d)
-                .await?;
-        }
-        KnownRecord::ChatBskyActorDeclaration(d) => {
-            let did_key = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), did_key);
-            let _: Option<Record> = db
-                .upsert(("lex_chat_bsky_actor_declaration", id))
-                .content(d)
-                .await?;
-        }
-        KnownRecord::AppBskyLabelerService(d) => {
-            let did_key = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), did_key);
-            let _: Option<Record> = db
-                .upsert(("lex_app_bsky_labeler_service", id))
-                .content(d)
-                .await?;
-        }
-        KnownRecord::AppBskyFeedPost(d) => {
-            let did_key = utils::did_to_key(did.as_str())?;
-            let id = format!("{}_{}", rkey.as_str(), did_key);
-
-            let mut images: Vec<BskyPostImage> = vec![];
-            let mut links: Vec<String> = vec![];
-            let mut mentions: Vec<RecordId> = vec![];
-            let mut record: Option<RecordId> = None;
-            let mut tags: Vec<String> = vec![];
-            let mut video: Option<BskyPostVideo> = None;
-
-            let mut post_images: Vec<atrium_api::app::bsky::embed::images::Image> = vec![];
-
-            match &d.embed {
-                Some(d) => {
-                    match d {
-                        atrium_api::types::Union::Refs(e) => {
-                            match e {
-                          atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedExternalMain(m)=>{
-                            // TODO index preview too
-                            links.push(m.external.uri.clone());
-                          },
-                            atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedImagesMain(m) => {
-                              post_images=m.images.clone();
-                            },
-                            atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedVideoMain(m) => {
-                              video = Some(process_video(m)?);
-                            },
-                            atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedRecordMain(m) => {
-                              record = Some(at_uri_to_record_id(&m.record.uri)?);
-                            },
-                            atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedRecordWithMediaMain(m) => {
-                              record = Some(at_uri_to_record_id(&m.record.record.uri)?);
-
-                              match &m.media{
-                                atrium_api::types::Union::Refs(r)=>match r{
-                                  atrium_api::app::bsky::embed::record_with_media::MainMediaRefs::AppBskyEmbedExternalMain(m)=>{
-                                    // TODO index preview too
-                                    links.push(m.external.uri.clone());
-                                  }
-                                  atrium_api::app::bsky::embed::record_with_media::MainMediaRefs::AppBskyEmbedImagesMain(m)=>{
-                                    post_images=m.images.clone();
-                                  }
-                                  atrium_api::app::bsky::embed::record_with_media::MainMediaRefs::AppBskyEmbedVideoMain(m)=>{
-
-                                    video = Some(process_video(m)?);
-                                  }
-                                }
-                                atrium_api::types::Union::Unknown(_)=>{}
-                              }
-                            },
+        let from = utils::at_uri_to_record_id(&d.list)?;
+        let to = utils::did_to_key(&d.subject)?;
+        let created_at = utils::extract_dt(&d.created_at)?;
+
+        let query = format!(
+            "RELATE {}->listitem->did:{} SET id = '{}', author = did:{}, createdAt = {};",
+            from, to, id, author, created_at
+        );
+
+        let _ = db.query(query).await?;
+
+        Ok(())
+    })
+}
+
+/// Store a feed generator and the service generating it
+pub(super) fn create_feed<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyFeedGenerator(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.feed.generator",
+                rkey.as_str()
+            );
+        };
+        let did_key = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), did_key);
+        let service_key = utils::did_to_key(d.did.as_str())?;
+        let feed = BskyFeed {
+            author: RecordId::from_table_key("did", did_key),
+            avatar: d.avatar.as_ref().map(blob_ref_to_record_id),
+            created_at: utils::extract_dt(&d.created_at)?,
+            description: d.description.clone(),
+            description_facets: d
+                .description_facets
+                .as_ref()
+                .map(|f| utils::extract_facets(f))
+                .transpose()?,
+            accepts_interactions: d.accepts_interactions,
+            content_mode: d.content_mode.clone(),
+            did: d.did.to_string(),
+            display_name: d.display_name.clone(),
+            rkey: rkey.to_string(),
+            uri: format!(
+                "at://{}/app.bsky.feed.generator/{}",
+                did.as_str(),
+                rkey.as_str()
+            ),
+            labels: d
+                .labels
+                .as_ref()
+                .and_then(|d| utils::extract_self_labels(d)),
+            extra_data: process_extra_data(&d.extra_data)?,
+        };
+        counters::upsert(db, &RecordId::from_table_key("feed", id.clone()), feed).await?;
+
+        // point the feed at the service that generates it
+        db.query("DELETE $edge; RELATE $feed->servedby->$service SET id = $id;")
+            .bind(("edge", RecordId::from_table_key("servedby", id.as_str())))
+            .bind(("feed", RecordId::from_table_key("feed", id.as_str())))
+            .bind(("service", RecordId::from_table_key("did", service_key)))
+            .bind(("id", id))
+            .await?
+            .check()?;
+
+        Ok(())
+    })
+}
+
+/// Store a list
+pub(super) fn create_list<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyGraphList(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.graph.list",
+                rkey.as_str()
+            );
+        };
+        let did_key = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), did_key);
+
+        let list = BskyList {
+            author: RecordId::from_table_key("did", did_key),
+            name: d.name.clone(),
+            avatar: d.avatar.as_ref().map(blob_ref_to_record_id),
+            created_at: utils::extract_dt(&d.created_at)?,
+            description: d.description.clone(),
+            description_facets: d
+                .description_facets
+                .as_ref()
+                .map(|f| utils::extract_facets(f))
+                .transpose()?,
+            labels: d
+                .labels
+                .as_ref()
+                .and_then(|d| utils::extract_self_labels(d)),
+            purpose: d.purpose.clone(),
+            extra_data: process_extra_data(&d.extra_data)?,
+        };
+        counters::upsert(db, &RecordId::from_table_key("list", id), list).await?;
+
+        Ok(())
+    })
+}
+
+/// Store a threadgate
+pub(super) fn create_threadgate<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyFeedThreadgate(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.feed.threadgate",
+                rkey.as_str()
+            );
+        };
+        let did_key = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), did_key);
+
+        let mut allow: Option<Vec<String>> = None;
+        let mut allow_lists: Vec<RecordId> = vec![];
+        if let Some(rules) = &d.allow {
+            let mut names = vec![];
+            for rule in rules {
+                match rule {
+                    atrium_api::types::Union::Refs(r) => match r {
+                        RecordAllowItem::AppBskyFeedThreadgateMentionRule(_) => {
+                            names.push("mention".to_string());
                         }
+                        RecordAllowItem::AppBskyFeedThreadgateFollowingRule(_) => {
+                            names.push("following".to_string());
                         }
-                        atrium_api::types::Union::Unknown(_) => {}
-                    }
+                        RecordAllowItem::AppBskyFeedThreadgateListRule(l) => {
+                            names.push("list".to_string());
+                            allow_lists.push(utils::at_uri_to_record_id(&l.list)?);
+                        }
+                    },
+                    atrium_api::types::Union::Unknown(_) => {}
                 }
-                None => {}
-            };
+            }
+            allow = Some(names);
+        }
+
+        let threadgate = BskyThreadgate {
+            author: RecordId::from_table_key("did", did_key),
+            post: utils::at_uri_to_record_id(&d.post)?,
+            allow,
+            allow_lists: if allow_lists.is_empty() {
+                None
+            } else {
+                Some(allow_lists)
+            },
+            hidden_replies: d
+                .hidden_replies
+                .as_ref()
+                .map(|h| {
+                    h.iter()
+                        .map(|uri| utils::at_uri_to_record_id(uri))
+                        .collect::<Result<Vec<RecordId>>>()
+                })
+                .transpose()?,
+            created_at: utils::extract_dt(&d.created_at)?,
+            extra_data: process_extra_data(&d.extra_data)?,
+        };
+        let _: Option<Record> = db
+            .upsert(("lex_app_bsky_feed_threadgate", id))
+            .content(threadgate)
+            .await?;
+
+        Ok(())
+    })
+}
+
+/// Store a starterpack and the lists and feeds it includes
+pub(super) fn create_starterpack<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyGraphStarterpack(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.graph.starterpack",
+                rkey.as_str()
+            );
+        };
+        let did_key = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), did_key);
+
+        let list = utils::at_uri_to_record_id(&d.list)?;
+        let feeds = d
+            .feeds
+            .as_ref()
+            .map(|feeds| {
+                feeds
+                    .iter()
+                    .map(|f| utils::at_uri_to_record_id(&f.uri))
+                    .collect::<Result<Vec<RecordId>>>()
+            })
+            .transpose()?;
+
+        let targets: Vec<RecordId> = std::iter::once(&list)
+            .chain(feeds.iter().flatten())
+            .cloned()
+            .collect();
+
+        let starterpack = BskyStarterpack {
+            author: RecordId::from_table_key("did", did_key),
+            name: d.name.clone(),
+            description: d.description.clone(),
+            list,
+            feeds,
+            created_at: utils::extract_dt(&d.created_at)?,
+            extra_data: process_extra_data(&d.extra_data)?,
+        };
+        let starterpack_id = RecordId::from_table_key("starterpack", id);
+        counters::upsert(db, &starterpack_id, starterpack).await?;
+
+        // replace the edges to the included list and feeds
+        db.query(
+            "DELETE $starterpack->includes; FOR $target IN $targets { RELATE $starterpack->includes->$target; };",
+        )
+        .bind(("starterpack", starterpack_id))
+        .bind(("targets", targets))
+        .await?
+        .check()?;
+
+        Ok(())
+    })
+}
 
-            if !post_images.is_empty() {
-                for i in post_images {
-                    images.push(BskyPostImage {
-                        alt: i.alt.clone(),
-                        blob: blob_ref_to_record_id(&i.image), // TODO store blob details
-                        aspect_ratio: i.aspect_ratio.as_ref().map(|a| BskyPostMediaAspectRatio {
-                            height: a.height.into(),
-                            width: a.width.into(),
-                        }),
+/// Store a postgate
+pub(super) fn create_postgate<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyFeedPostgate(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.feed.postgate",
+                rkey.as_str()
+            );
+        };
+        let did_key = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), did_key);
+        let postgate = BskyPostgate {
+            author: RecordId::from_table_key("did", did_key),
+            post: utils::at_uri_to_record_id(&d.post)?,
+            detached_embeddings: d
+                .detached_embedding_uris
+                .as_ref()
+                .map(|uris| {
+                    uris.iter()
+                        .map(|uri| utils::at_uri_to_record_id(uri))
+                        .collect::<Result<Vec<RecordId>>>()
+                })
+                .transpose()?,
+            embedding_disabled: d.embedding_rules.as_ref().is_some_and(|rules| {
+                rules.iter().any(|rule| {
+                    matches!(
+                        rule,
+                        atrium_api::types::Union::Refs(
+                            RecordEmbeddingRulesItem::AppBskyFeedPostgateDisableRule(_)
+                        )
+                    )
+                })
+            }),
+            created_at: utils::extract_dt(&d.created_at)?,
+            extra_data: process_extra_data(&d.extra_data)?,
+        };
+        let _: Option<Record> = db
+            .upsert(("lex_app_bsky_feed_postgate", id))
+            .content(postgate)
+            .await?;
+
+        Ok(())
+    })
+}
+
+/// Store a chat declaration
+pub(super) fn create_chat_declaration<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::ChatBskyActorDeclaration(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type chat.bsky.actor.declaration",
+                rkey.as_str()
+            );
+        };
+        let did_key = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), did_key);
+        let declaration = ChatDeclaration {
+            author: RecordId::from_table_key("did", did_key),
+            allow_incoming: d.allow_incoming.clone(),
+            extra_data: process_extra_data(&d.extra_data)?,
+        };
+        let _: Option<Record> = db
+            .upsert(("lex_chat_bsky_actor_declaration", id))
+            .content(declaration)
+            .await?;
+
+        Ok(())
+    })
+}
+
+/// Store a labeler service
+pub(super) fn create_labeler<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyLabelerService(d) = record else {
+            anyhow::bail!(
+                "Record {} is not of type app.bsky.labeler.service",
+                rkey.as_str()
+            );
+        };
+        let did_key = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), did_key);
+        let labeler = BskyLabeler {
+            author: RecordId::from_table_key("did", did_key),
+            label_values: d.policies.label_values.clone(),
+            label_value_definitions: d.policies.label_value_definitions.as_ref().map(|defs| {
+                defs.iter()
+                    .map(|def| BskyLabelValueDefinition {
+                        identifier: def.identifier.clone(),
+                        severity: def.severity.clone(),
+                        blurs: def.blurs.clone(),
+                        default_setting: def.default_setting.clone(),
+                        adult_only: def.adult_only,
+                        locales: def
+                            .locales
+                            .iter()
+                            .map(|l| BskyLabelValueDefinitionStrings {
+                                lang: l.lang.as_ref().to_string(),
+                                name: l.name.clone(),
+                                description: l.description.clone(),
+                            })
+                            .collect(),
                     })
-                }
-            }
+                    .collect()
+            }),
+            labels: d
+                .labels
+                .as_ref()
+                .and_then(|d| utils::extract_self_labels(d)),
+            created_at: utils::extract_dt(&d.created_at)?,
+            extra_data: process_extra_data(&d.extra_data)?,
+        };
+        counters::upsert(db, &RecordId::from_table_key("labeler", id), labeler).await?;
 
-            if let Some(r) = &record {
-                if r.table() == "post" {
-                    let query = format!(
-                        "RELATE post:{}->quotes->post:{} SET id = '{}';",
-                        id,
-                        r.key(),
-                        id
-                    );
+        Ok(())
+    })
+}
 
-                    let _ = db.query(query).await?;
-                }
-            }
+/// Store a post and its reply and quote edges
+pub(super) fn create_post<'a>(
+    db: &'a Surreal<Any>,
+    did: &'a Did,
+    rkey: &'a RecordKey,
+    record: KnownRecord,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        let KnownRecord::AppBskyFeedPost(d) = record else {
+            anyhow::bail!("Record {} is not of type app.bsky.feed.post", rkey.as_str());
+        };
+        let did_key = utils::did_to_key(did.as_str())?;
+        let id = format!("{}_{}", rkey.as_str(), did_key);
 
-            if let Some(facets) = &d.facets {
-                for facet in facets {
-                    for feature in &facet.features {
-                        match feature {
-                            atrium_api::types::Union::Refs(refs) => match refs {
-                                MainFeaturesItem::Mention(m) => {
-                                    mentions.push(("did", did_to_key(m.did.as_str())?).into());
-                                }
-                                MainFeaturesItem::Link(l) => {
-                                    links.push(l.uri.clone());
-                                }
-                                MainFeaturesItem::Tag(t) => {
-                                    tags.push(t.tag.clone());
-                                }
-                            },
-                            atrium_api::types::Union::Unknown(_) => {}
-                        }
+        let mut images: Vec<BskyPostImage> = vec![];
+        let mut links: Vec<String> = vec![];
+        let mut mentions: Vec<RecordId> = vec![];
+        let mut record: Option<RecordId> = None;
+        let mut tags: Vec<String> = vec![];
+        let mut video: Option<BskyPostVideo> = None;
+
+        let mut post_images: Vec<atrium_api::app::bsky::embed::images::Image> = vec![];
+
+        match &d.embed {
+            Some(d) => {
+                match d {
+                    atrium_api::types::Union::Refs(e) => {
+                        match e {
+                      atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedExternalMain(m)=>{
+                        // TODO index preview too
+                        links.push(m.external.uri.clone());
+                      },
+                        atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedImagesMain(m) => {
+                          post_images=m.images.clone();
+                        },
+                        atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedVideoMain(m) => {
+                          video = Some(process_video(m)?);
+                        },
+                        atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedRecordMain(m) => {
+                          record = Some(at_uri_to_record_id(&m.record.uri)?);
+                        },
+                        atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedRecordWithMediaMain(m) => {
+                          record = Some(at_uri_to_record_id(&m.record.record.uri)?);
+
+                          match &m.media{
+                            atrium_api::types::Union::Refs(r)=>match r{
+                              atrium_api::app::bsky::embed::record_with_media::MainMediaRefs::AppBskyEmbedExternalMain(m)=>{
+                                // TODO index preview too
+                                links.push(m.external.uri.clone());
+                              }
+                              atrium_api::app::bsky::embed::record_with_media::MainMediaRefs::AppBskyEmbedImagesMain(m)=>{
+                                post_images=m.images.clone();
+                              }
+                              atrium_api::app::bsky::embed::record_with_media::MainMediaRefs::AppBskyEmbedVideoMain(m)=>{
+
+                                video = Some(process_video(m)?);
+                              }
+                            }
+                            atrium_api::types::Union::Unknown(_)=>{}
+                          }
+                        },
                     }
+                    }
+                    atrium_api::types::Union::Unknown(_) => {}
                 }
             }
+            None => {}
+        };
 
-            if let Some(t) = &d.tags {
-                tags.extend(t.clone());
+        if !post_images.is_empty() {
+            for i in post_images {
+                images.push(BskyPostImage {
+                    alt: i.alt.clone(),
+                    blob: blob_ref_to_record_id(&i.image), // TODO store blob details
+                    aspect_ratio: i.aspect_ratio.as_ref().map(|a| BskyPostMediaAspectRatio {
+                        height: a.height.into(),
+                        width: a.width.into(),
+                    }),
+                })
             }
+        }
 
-            let post = BskyPost {
-                author: RecordId::from_table_key("did", did_key.clone()),
-                bridgy_original_url: None,
-                via: None,
-                created_at: utils::extract_dt(&d.created_at)?,
-                labels: d
-                    .labels
-                    .as_ref()
-                    .and_then(|d| utils::extract_self_labels_post(d)),
-                text: d.text.clone(),
-                langs: d
-                    .langs
-                    .as_ref()
-                    .map(|d| d.iter().map(|l| l.as_ref().to_string()).collect()),
-                root: d
-                    .reply
-                    .as_ref()
-                    .map(|r| utils::strong_ref_to_record_id(&r.root))
-                    .transpose()?,
-                parent: d
-                    .reply
-                    .as_ref()
-                    .map(|r| utils::strong_ref_to_record_id(&r.parent))
-                    .transpose()?,
-                video: video,
-                tags: if tags.is_empty() { None } else { Some(tags) },
-                links: if links.is_empty() { None } else { Some(links) },
-                mentions: if mentions.is_empty() {
-                    None
-                } else {
-                    Some(mentions)
-                },
-                record: record,
-                images: if images.is_empty() {
-                    None
-                } else {
-                    Some(images)
-                },
-                extra_data: process_extra_data(&d.extra_data)?,
-            };
-            let parent = post.parent.clone();
-            let _: Option<Record> = db.upsert(("post", id.clone())).content(post).await?;
-
-            if parent.is_some() {
-                let query1 = format!(
-                    "RELATE did:{}->replies->post:{} SET id = '{}';",
-                    did_key, id, id
-                );
-                let _ = db.query(query1).await?;
-
-                let query2 = format!(
-                    "RELATE post:{}->replyto->{} SET id = '{}';",
-                    id,
-                    parent.unwrap(),
-                    id
-                );
-                let _ = db.query(query2).await?;
-            } else {
-                let query = format!(
-                    "RELATE did:{}->posts->post:{} SET id = '{}';",
-                    did_key, id, id
-                );
-                let _ = db.query(query).await?;
+        if let Some(r) = &record {
+            if r.table() == "post" {
+                counters::relate(
+                    db,
+                    &RecordId::from_table_key("post", id.clone()),
+                    "quotes",
+                    r,
+                    &id,
+                    "",
+                )
+                .await?;
             }
         }
-        _ => {
-            warn!(target: "indexer", "ignored create_or_update {} {} {}",
-                did.as_str(), collection, rkey.as_str());
+
+        if let Some(facets) = &d.facets {
+            let facets = utils::extract_facets(facets)?;
+            mentions.extend(facets.mentions);
+            links.extend(facets.links);
+            tags.extend(facets.tags);
         }
-    }
 
-    Ok(())
+        if let Some(t) = &d.tags {
+            tags.extend(t.clone());
+        }
+
+        let post = BskyPost {
+            author: RecordId::from_table_key("did", did_key.clone()),
+            bridgy_original_url: None,
+            via: None,
+            created_at: utils::extract_dt(&d.created_at)?,
+            labels: d
+                .labels
+                .as_ref()
+                .and_then(|d| utils::extract_self_labels(d)),
+            text: d.text.clone(),
+            langs: d
+                .langs
+                .as_ref()
+                .map(|d| d.iter().map(|l| l.as_ref().to_string()).collect()),
+            root: d
+                .reply
+                .as_ref()
+                .map(|r| utils::strong_ref_to_record_id(&r.root))
+                .transpose()?,
+            parent: d
+                .reply
+                .as_ref()
+                .map(|r| utils::strong_ref_to_record_id(&r.parent))
+                .transpose()?,
+            video: video,
+            tags: if tags.is_empty() { None } else { Some(tags) },
+            links: if links.is_empty() { None } else { Some(links) },
+            mentions: if mentions.is_empty() {
+                None
+            } else {
+                Some(mentions)
+            },
+            record: record,
+            images: if images.is_empty() {
+                None
+            } else {
+                Some(images)
+            },
+            extra_data: process_extra_data(&d.extra_data)?,
+        };
+        let parent = post.parent.clone();
+        let root = post.root.clone();
+        counters::upsert(db, &RecordId::from_table_key("post", id.clone()), post).await?;
+
+        if let Some(parent) = parent {
+            let query1 = format!(
+                "RELATE did:{}->replies->post:{} SET id = '{}';",
+                did_key, id, id
+            );
+            let _ = db.query(query1).await?;
+
+            counters::relate(
+                db,
+                &RecordId::from_table_key("post", id.clone()),
+                "replyto",
+                &parent,
+                &id,
+                "",
+            )
+            .await?;
+
+            if let Some(root) = root {
+                db.query("RELATE $post->thread->$root SET id = $id;")
+                    .bind(("post", RecordId::from_table_key("post", id.as_str())))
+                    .bind(("root", root))
+                    .bind(("id", id))
+                    .await?
+                    .check()?;
+            }
+        } else {
+            let query = format!(
+                "RELATE did:{}->posts->post:{} SET id = '{}';",
+                did_key, id, id
+            );
+            let _ = db.query(query).await?;
+        }
+
+        Ok(())
+    })
 }
 
 fn process_video(vid: &video::Main) -> Result<BskyPostVideo> {
@@ -509,68 +980,81 @@ fn extract_video_blob(blob: &BlobRef) -> Result<Blob> {
 }
 
 /// If the new commit is a delete, handle it
-async fn on_commit_event_delete(
+pub async fn on_commit_event_delete(
     db: &Surreal<Any>,
     did: Did,
     _time_us: u64,
-    _did_key: String,
+    did_key: String,
     _rev: String,
     collection: String,
     rkey: RecordKey,
 ) -> Result<()> {
     utils::ensure_valid_rkey(rkey.to_string())?;
 
-    let id = format!("{}_{}", rkey.as_str(), utils::did_to_key(did.as_str())?);
-    match collection.as_str() {
-        "app.bsky.graph.follow" => {
-            delete_record(db, "follow", &id).await?;
-        }
-        "app.bsky.feed.repost" => {
-            delete_record(db, "repost", &id).await?;
-        }
-        "app.bsky.feed.like" => {
-            delete_record(db, "like", &id).await?;
-        }
-        "app.bsky.graph.block" => {
-            delete_record(db, "block", &id).await?;
-        }
-        "app.bsky.graph.listblock" => {
-            delete_record(db, "listblock", &id).await?;
+    let Some(c) = collections::lookup(&collection) else {
+        warn!(target: "indexer", "could not handle operation {} {} {} {}",
+            did.as_str(), "delete", collection, rkey.as_str());
+        return Ok(());
+    };
+
+    let id = format!("{}_{}", rkey.as_str(), did_key);
+    match c.nsid {
+        // the profile lives on the did row itself
+        "app.bsky.actor.profile" => {
+            return clear_profile(db, &did_key).await;
         }
         "app.bsky.feed.post" => {
-            for table in vec!["post", "posts", "replies", "replyto", "quotes"] {
-                delete_record(db, table, &id).await?;
-            }
-        }
-        "app.bsky.graph.listitem" => {
-            delete_record(db, "listitem", &id).await?;
-        }
-        "app.bsky.feed.threadgate" => {
-            delete_record(db, "threadgate", &id).await?;
-        }
-        "app.bsky.feed.generator" => {
-            delete_record(db, "feed", &id).await?;
-        }
-        "app.bsky.graph.list" => {
-            delete_record(db, "list", &id).await?;
-        }
-        "app.bsky.feed.postgate" => {
-            delete_record(db, "postgate", &id).await?;
+            cascade_post_delete(db, &id).await?;
         }
         "app.bsky.graph.starterpack" => {
-            delete_record(db, "starterpack", &id).await?;
-        }
-        "app.bsky.labeler.service" => {
-            delete_record(db, "labeler", &id).await?;
-        }
-        "chat.bsky.actor.declaration" => {
-            delete_record(db, "chat_bsky_actor_declaration", &id).await?;
-        }
-        _ => {
-            warn!(target: "indexer", "could not handle operation {} {} {} {}",
-                did.as_str(), "delete", collection, rkey.as_str());
+            db.query("DELETE $starterpack->includes;")
+                .bind((
+                    "starterpack",
+                    RecordId::from_table_key("starterpack", id.as_str()),
+                ))
+                .await?
+                .check()?;
         }
+        _ => {}
+    }
+
+    for table in c.edges {
+        counters::delete_record(db, table, &id).await?;
     }
+    counters::delete_record(db, c.table, &id).await?;
+
+    Ok(())
+}
+
+/// Apply the configured cascade policy to edges referencing a deleted post
+async fn cascade_post_delete(db: &Surreal<Any>, id: &str) -> Result<()> {
+    let query: String = match POST_DELETE_CASCADE.get().copied().unwrap_or_default() {
+        PostDeleteCascade::Keep => return Ok(()),
+        PostDeleteCascade::Orphan => POST_REFERENCES
+            .iter()
+            .map(|table| format!("UPDATE $post<-{} SET orphaned = true;", table))
+            .collect(),
+        PostDeleteCascade::Delete => POST_REFERENCES
+            .iter()
+            .map(|table| format!("DELETE $post<-{};", table))
+            .collect(),
+    };
+    db.query(query)
+        .bind(("post", RecordId::from_table_key("post", id)))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+/// Remove all profile fields from a did, keeping the rest of the row
+async fn clear_profile(db: &Surreal<Any>, did_key: &str) -> Result<()> {
+    let query = format!(
+        "UPDATE did:{} UNSET {};",
+        did_key,
+        BskyProfile::FIELDS.join(", ")
+    );
+    let _ = db.query(query).await?;
 
     Ok(())
 }
@@ -0,0 +1,192 @@
+use anyhow::Result;
+use log::{debug, info};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use surrealdb::{engine::any::Any, RecordId, Surreal};
+
+/// A secondary index managed by the indexer
+#[derive(Debug)]
+pub struct Index {
+    /// Name of the index
+    pub name: &'static str,
+    /// Table the index is defined on
+    pub table: &'static str,
+    /// Definition following `DEFINE INDEX <name> ON TABLE <table>`
+    pub definition: &'static str,
+    /// Whether the index is costly to build and maintain
+    pub expensive: bool,
+}
+
+/// All managed indexes
+pub const INDEXES: &[Index] = &[
+    Index {
+        name: "post_created_at",
+        table: "post",
+        definition: "FIELDS createdAt",
+        expensive: false,
+    },
+    Index {
+        name: "post_author",
+        table: "post",
+        definition: "FIELDS author, createdAt",
+        expensive: false,
+    },
+    Index {
+        name: "post_langs",
+        table: "post",
+        definition: "FIELDS langs",
+        expensive: true,
+    },
+    Index {
+        name: "post_tags",
+        table: "post",
+        definition: "FIELDS tags",
+        expensive: true,
+    },
+    Index {
+        name: "post_text_search",
+        table: "post",
+        definition: "FIELDS text SEARCH ANALYZER post_text BM25 HIGHLIGHTS",
+        expensive: true,
+    },
+    Index {
+        name: "post_text_search_en",
+        table: "post",
+        definition: "FIELDS textEn SEARCH ANALYZER post_text_en BM25 HIGHLIGHTS",
+        expensive: true,
+    },
+    Index {
+        name: "post_text_search_de",
+        table: "post",
+        definition: "FIELDS textDe SEARCH ANALYZER post_text_de BM25 HIGHLIGHTS",
+        expensive: true,
+    },
+    Index {
+        name: "post_text_search_fr",
+        table: "post",
+        definition: "FIELDS textFr SEARCH ANALYZER post_text_fr BM25 HIGHLIGHTS",
+        expensive: true,
+    },
+    Index {
+        name: "post_text_search_es",
+        table: "post",
+        definition: "FIELDS textEs SEARCH ANALYZER post_text_es BM25 HIGHLIGHTS",
+        expensive: true,
+    },
+    Index {
+        name: "post_text_search_pt",
+        table: "post",
+        definition: "FIELDS textPt SEARCH ANALYZER post_text_pt BM25 HIGHLIGHTS",
+        expensive: true,
+    },
+    Index {
+        name: "post_text_search_nl",
+        table: "post",
+        definition: "FIELDS textNl SEARCH ANALYZER post_text_nl BM25 HIGHLIGHTS",
+        expensive: true,
+    },
+    Index {
+        name: "post_text_search_it",
+        table: "post",
+        definition: "FIELDS textIt SEARCH ANALYZER post_text_it BM25 HIGHLIGHTS",
+        expensive: true,
+    },
+    Index {
+        name: "did_display_name_search",
+        table: "did",
+        definition: "FIELDS displayName SEARCH ANALYZER profile_text BM25 HIGHLIGHTS",
+        expensive: true,
+    },
+    Index {
+        name: "did_description_search",
+        table: "did",
+        definition: "FIELDS description SEARCH ANALYZER profile_text BM25 HIGHLIGHTS",
+        expensive: true,
+    },
+    Index {
+        name: "did_handle",
+        table: "did",
+        definition: "FIELDS handle",
+        expensive: false,
+    },
+];
+
+/// Options selecting which managed indexes are defined
+#[derive(Debug, Default)]
+pub struct IndexOptions {
+    /// Skip all indexes marked as expensive
+    pub skip_expensive: bool,
+    /// Names of indexes to skip
+    pub disabled: Vec<String>,
+}
+
+impl IndexOptions {
+    /// Check whether an index should be defined
+    fn enabled(&self, index: &Index) -> bool {
+        !(self.skip_expensive && index.expensive) && !self.disabled.iter().any(|n| n == index.name)
+    }
+}
+
+/// Database struct for the definition a managed index was last defined with
+#[derive(Debug, Serialize)]
+struct ManagedIndex {
+    id: RecordId,
+    definition: String,
+}
+
+/// Define all enabled indexes and remove all disabled ones, or only print
+/// the statements on a dry run
+///
+/// Indexes whose definition changed since they were last defined are
+/// redefined, which rebuilds them.
+pub async fn sync(db: &Surreal<Any>, options: &IndexOptions, dry_run: bool) -> Result<()> {
+    if let Some(unknown) = options
+        .disabled
+        .iter()
+        .find(|n| !INDEXES.iter().any(|i| i.name == n.as_str()))
+    {
+        anyhow::bail!("Unknown index {}", unknown);
+    }
+
+    let previous: Vec<(String, String)> = db
+        .query("SELECT VALUE [record::id(id), definition] FROM managed_index;")
+        .await?
+        .take(0)?;
+    let previous: BTreeMap<String, String> = previous.into_iter().collect();
+
+    let mut query = String::new();
+    let mut defined = Vec::new();
+    for index in INDEXES {
+        if options.enabled(index) {
+            let mode = match previous.get(index.name) {
+                Some(definition) if definition == index.definition => "IF NOT EXISTS",
+                _ => "OVERWRITE",
+            };
+            query.push_str(&format!(
+                "DEFINE INDEX {} {} ON TABLE {} {};\n",
+                mode, index.name, index.table, index.definition
+            ));
+            defined.push(ManagedIndex {
+                id: RecordId::from_table_key("managed_index", index.name),
+                definition: index.definition.to_string(),
+            });
+        } else {
+            query.push_str(&format!(
+                "REMOVE INDEX IF EXISTS {} ON TABLE {};\n",
+                index.name, index.table
+            ));
+        }
+    }
+    query.push_str("DELETE managed_index;\nINSERT INTO managed_index $defined;\n");
+
+    if dry_run {
+        println!("-- indexes\n{}", query);
+        return Ok(());
+    }
+
+    info!(target: "indexer", "Updating indexes, this may take a while on large databases");
+    debug!(target: "indexer", "Index statements:\n{}", query);
+    db.query(query).bind(("defined", defined)).await?.check()?;
+
+    Ok(())
+}
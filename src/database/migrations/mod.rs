@@ -0,0 +1,248 @@
+use anyhow::Context;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use surrealdb::{engine::any::Any, Datetime, Surreal};
+
+/// A numbered schema migration
+#[derive(Debug)]
+pub struct Migration {
+    /// Schema version after the migration has been applied
+    pub version: u32,
+    /// Short description of the migration
+    pub name: &'static str,
+    /// SurrealQL executed by the migration
+    pub query: &'static str,
+    /// Whether the engagement counters are recomputed after the migration
+    pub repair_counters: bool,
+    /// Tables whose rows are rewritten after the migration, so computed
+    /// fields are filled in for rows stored before they were defined
+    pub recompute: &'static [&'static str],
+}
+
+impl Migration {
+    /// Whether the migration is followed by steps run outside of its transaction
+    fn has_followups(&self) -> bool {
+        self.repair_counters || !self.recompute.is_empty()
+    }
+}
+
+/// All migrations, in the order they are applied
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        query: include_str!("0001_initial.surql"),
+        repair_counters: true,
+        recompute: &[],
+    },
+    Migration {
+        version: 2,
+        name: "complete_schema",
+        query: include_str!("0002_complete_schema.surql"),
+        repair_counters: false,
+        recompute: &[],
+    },
+    Migration {
+        version: 3,
+        name: "search_analyzers",
+        query: include_str!("0003_search_analyzers.surql"),
+        repair_counters: false,
+        recompute: &[],
+    },
+    Migration {
+        version: 4,
+        name: "full_text_search",
+        query: include_str!("0004_full_text_search.surql"),
+        repair_counters: false,
+        recompute: &["post"],
+    },
+    Migration {
+        version: 5,
+        name: "did_cache",
+        query: include_str!("0005_did_cache.surql"),
+        repair_counters: false,
+        recompute: &[],
+    },
+    Migration {
+        version: 6,
+        name: "backfill_seeds",
+        query: include_str!("0006_backfill_seeds.surql"),
+        repair_counters: false,
+        recompute: &[],
+    },
+    Migration {
+        version: 7,
+        name: "recrawl",
+        query: include_str!("0007_recrawl.surql"),
+        repair_counters: false,
+        recompute: &[],
+    },
+    Migration {
+        version: 8,
+        name: "record_owners",
+        query: include_str!("0008_record_owners.surql"),
+        repair_counters: false,
+        recompute: &[],
+    },
+];
+
+/// Database struct for the schema version
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SchemaVersion {
+    pub version: u32,
+    pub name: String,
+    #[serde(rename = "appliedAt")]
+    pub applied_at: Datetime,
+}
+
+/// Fetch the schema version of the database, if any migration was applied
+pub async fn current_version(db: &Surreal<Any>) -> anyhow::Result<Option<SchemaVersion>> {
+    let res: Option<SchemaVersion> = db.select(("schema_version", "current")).await?;
+
+    Ok(res)
+}
+
+/// Get the migrations not applied to a database at the given version yet
+fn pending(version: u32) -> anyhow::Result<&'static [Migration]> {
+    let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+    if version > latest {
+        anyhow::bail!(
+            "Database schema version {} is newer than the latest known version {}",
+            version,
+            latest
+        );
+    }
+
+    Ok(&MIGRATIONS[MIGRATIONS.partition_point(|m| m.version <= version)..])
+}
+
+/// Build the SurrealQL recording the version of a migration as applied
+fn version_query(migration: &Migration) -> String {
+    format!(
+        "UPSERT schema_version:current CONTENT {{ version: {}, name: '{}', appliedAt: time::now() }};\n",
+        migration.version, migration.name
+    )
+}
+
+/// Build the SurrealQL applying a migration and recording the new version
+///
+/// Migrations followed by a counter repair or rewritten tables only record
+/// their version once these succeeded, so a failure is retried with the migration.
+fn migration_query(migration: &Migration) -> String {
+    let version = if migration.has_followups() {
+        String::new()
+    } else {
+        version_query(migration)
+    };
+
+    format!(
+        "BEGIN TRANSACTION;\n{}\n{}COMMIT TRANSACTION;\n",
+        migration.query, version
+    )
+}
+
+/// Apply all pending migrations, or only print them on a dry run
+pub async fn up(db: &Surreal<Any>, dry_run: bool) -> anyhow::Result<()> {
+    let version = current_version(db).await?.map_or(0, |v| v.version);
+    let pending = pending(version)?;
+    if pending.is_empty() {
+        info!(target: "indexer", "Database schema is up to date at version {}", version);
+        return Ok(());
+    }
+
+    for migration in pending {
+        let query = migration_query(migration);
+        if dry_run {
+            println!(
+                "-- migration {:04}_{}\n{}",
+                migration.version, migration.name, query
+            );
+            for table in migration.recompute {
+                println!("-- followed by rewriting all rows of table {}", table);
+            }
+            if migration.repair_counters {
+                println!("-- followed by recomputing all engagement counters");
+            }
+            if migration.has_followups() {
+                println!("{}", version_query(migration));
+            }
+            continue;
+        }
+
+        info!(target: "indexer", "Applying migration {:04}_{}", migration.version, migration.name);
+        db.query(query)
+            .await
+            .and_then(|res| res.check())
+            .with_context(|| {
+                format!(
+                    "Failed to apply migration {:04}_{}",
+                    migration.version, migration.name
+                )
+            })?;
+
+        for table in migration.recompute {
+            info!(target: "indexer", "Rewriting all rows of table {}", table);
+            super::update_in_batches(db, table, "UPDATE $ids RETURN NONE;")
+                .await
+                .with_context(|| format!("Failed to rewrite table {}", table))?;
+        }
+        if migration.repair_counters {
+            super::counters::repair(db)
+                .await
+                .context("Failed to recompute counters")?;
+        }
+        if migration.has_followups() {
+            db.query(version_query(migration))
+                .await
+                .and_then(|res| res.check())
+                .context("Failed to record the schema version")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Log the schema version of the database and all pending migrations
+pub async fn status(db: &Surreal<Any>) -> anyhow::Result<()> {
+    let current = current_version(db).await?;
+    match &current {
+        Some(v) => info!(target: "indexer", "Database schema at version {:04}_{} (applied {})",
+            v.version, v.name, v.applied_at),
+        None => info!(target: "indexer", "Database schema is not versioned yet"),
+    }
+
+    let pending = pending(current.map_or(0, |v| v.version))?;
+    if pending.is_empty() {
+        info!(target: "indexer", "No pending migrations");
+    }
+    for migration in pending {
+        warn!(target: "indexer", "Pending migration {:04}_{}", migration.version, migration.name);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_contiguous() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(
+                migration.version as usize,
+                i + 1,
+                "migration {} is out of order",
+                migration.name
+            );
+        }
+    }
+
+    #[test]
+    fn pending_skips_applied_migrations() {
+        let latest = MIGRATIONS.len() as u32;
+        assert_eq!(pending(0).unwrap().len(), MIGRATIONS.len());
+        assert!(pending(latest).unwrap().is_empty());
+        assert!(pending(latest + 1).is_err());
+    }
+}
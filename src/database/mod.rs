@@ -1,30 +1,226 @@
 use anyhow::{Context, Result};
 use definitions::{JetstreamCursor, Record};
-use log::info;
-use surrealdb::{engine::any::Any, RecordId, Surreal};
+use indexes::IndexOptions;
+use log::{debug, info};
+use surrealdb::{
+    engine::any::Any,
+    opt::auth::{Database, Namespace, Root},
+    RecordId, Surreal,
+};
 
+use crate::config::AuthLevel;
+
+mod collections;
+pub mod counters;
 pub mod definitions;
 pub mod handlers;
+pub mod indexes;
+pub mod migrations;
 pub mod repo_indexer;
-mod utils;
+pub mod search;
+pub mod utils;
+
+/// A storage engine the database can be accessed through
+struct Engine {
+    /// Name of the engine
+    name: &'static str,
+    /// Endpoint schemes handled by the engine
+    schemes: &'static [&'static str],
+    /// Cargo feature enabling the engine, if it is optional
+    feature: Option<&'static str>,
+    /// Whether the engine was compiled in
+    enabled: bool,
+}
+
+/// All storage engines known to the indexer
+const ENGINES: &[Engine] = &[
+    Engine {
+        name: "Memory",
+        schemes: &["mem"],
+        feature: Some("kv-mem"),
+        enabled: cfg!(feature = "kv-mem"),
+    },
+    Engine {
+        name: "RocksDB",
+        schemes: &["rocksdb"],
+        feature: Some("kv-rocksdb"),
+        enabled: cfg!(feature = "kv-rocksdb"),
+    },
+    Engine {
+        name: "SurrealKV",
+        schemes: &["surrealkv", "surrealkv+versioned"],
+        feature: Some("kv-surrealkv"),
+        enabled: cfg!(feature = "kv-surrealkv"),
+    },
+    Engine {
+        name: "TiKV",
+        schemes: &["tikv"],
+        feature: Some("kv-tikv"),
+        enabled: cfg!(feature = "kv-tikv"),
+    },
+    Engine {
+        name: "Remote",
+        schemes: &["ws", "wss", "http", "https"],
+        feature: None,
+        enabled: true,
+    },
+];
+
+/// Ensure the engine required by a database endpoint was compiled in
+fn check_engine(db_endpoint: &str) -> Result<()> {
+    let compiled: Vec<&str> = ENGINES
+        .iter()
+        .filter(|e| e.enabled)
+        .map(|e| e.name)
+        .collect();
+    info!(target: "indexer", "Compiled in storage engines: {}", compiled.join(", "));
+
+    let Some((scheme, _)) = db_endpoint.split_once("://") else {
+        anyhow::bail!("Database endpoint {} is missing a scheme", db_endpoint);
+    };
+    let Some(engine) = ENGINES.iter().find(|e| e.schemes.contains(&scheme)) else {
+        anyhow::bail!("Unsupported database endpoint scheme {}://", scheme);
+    };
+    if !engine.enabled {
+        anyhow::bail!(
+            "The {} storage engine was not compiled in, rebuild with `--features {}`",
+            engine.name,
+            engine.feature.unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+/// Credentials used to authenticate with the database server
+#[derive(Debug)]
+pub enum Credentials {
+    /// Don't authenticate (e.g. for embedded databases)
+    None,
+    /// Sign in as a user defined on the given level
+    User {
+        level: AuthLevel,
+        username: String,
+        password: String,
+    },
+    /// Authenticate with a token
+    Token(String),
+}
 
 /// Connect to the database
 pub async fn connect(
     db_endpoint: String,
+    namespace: &str,
+    database: &str,
+    credentials: Credentials,
 ) -> anyhow::Result<Surreal<Any>> {
     // connect to the database
+    check_engine(&db_endpoint)?;
     info!(target: "indexer", "Connecting to the database at {}", db_endpoint);
     let db = surrealdb::engine::any::connect(db_endpoint).await?;
 
     // sign in to the server
+    match &credentials {
+        Credentials::None => {}
+        Credentials::User {
+            level,
+            username,
+            password,
+        } => {
+            info!(target: "indexer", "Signing in to the database as {} ({:?} level)", username, level);
+            match level {
+                AuthLevel::Root => db.signin(Root { username, password }).await.map(|_| ()),
+                AuthLevel::Namespace => db
+                    .signin(Namespace {
+                        namespace,
+                        username,
+                        password,
+                    })
+                    .await
+                    .map(|_| ()),
+                AuthLevel::Database => db
+                    .signin(Database {
+                        namespace,
+                        database,
+                        username,
+                        password,
+                    })
+                    .await
+                    .map(|_| ()),
+            }
+            .context("Failed to sign in to the database")?;
+        }
+        Credentials::Token(token) => {
+            info!(target: "indexer", "Authenticating to the database with a token");
+            db.authenticate(token.clone())
+                .await
+                .context("Failed to authenticate to the database")?;
+        }
+    }
 
-    definitions::init(&db)
+    definitions::init(&db, namespace, database)
         .await
-        .context("Failed to initialize database schema")?;
+        .context("Failed to initialize database namespace")?;
 
     Ok(db)
 }
 
+/// Apply all pending migrations
+pub async fn migrate(db: &Surreal<Any>, dry_run: bool) -> Result<()> {
+    migrations::up(db, dry_run)
+        .await
+        .context("Failed to migrate database schema")?;
+
+    if !dry_run {
+        definitions::verify_tables(db)
+            .await
+            .context("Database schema is incomplete")?;
+    }
+
+    Ok(())
+}
+
+/// Define the enabled managed indexes and remove the disabled ones
+pub async fn sync_indexes(
+    db: &Surreal<Any>,
+    index_options: &IndexOptions,
+    dry_run: bool,
+) -> Result<()> {
+    indexes::sync(db, index_options, dry_run)
+        .await
+        .context("Failed to update indexes")
+}
+
+/// Amount of rows updated at once by [update_in_batches]
+const UPDATE_BATCH_SIZE: usize = 1000;
+
+/// Run an update over all rows of a table in batches ordered by id
+///
+/// The ids of every batch are bound to `$ids` in the query.
+pub async fn update_in_batches(db: &Surreal<Any>, table: &str, query: &str) -> Result<()> {
+    let mut after: Option<RecordId> = None;
+    let mut total = 0;
+    loop {
+        let ids: Vec<RecordId> = db
+            .query("SELECT VALUE id FROM type::table($table) WHERE $after = NONE OR id > $after ORDER BY id LIMIT $limit")
+            .bind(("table", table.to_string()))
+            .bind(("after", after.clone()))
+            .bind(("limit", UPDATE_BATCH_SIZE))
+            .await?
+            .take(0)?;
+        let Some(last) = ids.last().cloned() else {
+            break;
+        };
+
+        total += ids.len();
+        db.query(query).bind(("ids", ids)).await?.check()?;
+        debug!(target: "indexer", "Updated {} {} records", total, table);
+        after = Some(last);
+    }
+
+    Ok(())
+}
+
 /// Fetch the current cursor from the database
 pub async fn fetch_cursor(db: &Surreal<Any>, host: &str) -> Result<Option<JetstreamCursor>> {
     let res: Option<JetstreamCursor> = db.select(("cursor", host)).await?;
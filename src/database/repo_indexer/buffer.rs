@@ -0,0 +1,202 @@
+use anyhow::Result;
+use log::warn;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Mutex, OnceLock},
+};
+use surrealdb::{engine::any::Any, Surreal};
+
+use crate::{
+    database::handlers::apply_event,
+    websocket::events::{Commit, Kind},
+};
+
+/// Live commit events of repos that are being backfilled, by DID
+static BUFFERS: OnceLock<Mutex<HashMap<String, Vec<Kind>>>> = OnceLock::new();
+
+/// Handle holding back the live events of a repo until it is replayed
+///
+/// The buffer has to be replayed on every path, including failed backfills.
+/// Release builds abort on panics, so nothing is replayed when dropping it.
+#[must_use = "held back events are only applied when the buffer is replayed"]
+pub struct Buffer {
+    db: Surreal<Any>,
+    did: String,
+}
+
+impl Buffer {
+    /// Apply the events held back while the repo was backfilled and stop holding them back
+    ///
+    /// Events at or before the revision of the applied snapshot are already part of it
+    /// and are dropped. Without a snapshot, all events are applied.
+    pub async fn replay(self, snapshot_rev: Option<&str>) {
+        replay(&self.db, &self.did, snapshot_rev).await;
+    }
+}
+
+/// Start holding back live commit events of a repo
+pub fn start(db: &Surreal<Any>, did: &str) -> Buffer {
+    BUFFERS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .insert(did.to_string(), Vec::new());
+
+    Buffer {
+        db: db.clone(),
+        did: did.to_string(),
+    }
+}
+
+/// Hold back a live event if its repo is being backfilled, otherwise hand it back
+pub fn intercept(event: Kind) -> Option<Kind> {
+    let Some(buffers) = BUFFERS.get() else {
+        return Some(event);
+    };
+    let Kind::CommitEvent { did, .. } = &event else {
+        return Some(event);
+    };
+
+    let mut buffers = buffers.lock().unwrap();
+    match buffers.get_mut(did.as_str()) {
+        Some(buffer) => {
+            buffer.push(event);
+            None
+        }
+        None => Some(event),
+    }
+}
+
+/// Apply the held back events of a repo on the database
+async fn replay(db: &Surreal<Any>, did: &str, snapshot_rev: Option<&str>) {
+    drain(did, snapshot_rev, |event| apply_event(db, event)).await;
+}
+
+/// Hand the held back events newer than the snapshot to `apply` until none are left
+async fn drain<F, Fut>(did: &str, snapshot_rev: Option<&str>, mut apply: F)
+where
+    F: FnMut(Kind) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    // events arriving during the replay are held back until the buffer drained,
+    // so they can't be overtaken by older ones
+    while let Some(events) = take(did) {
+        for event in events {
+            if !is_newer(&event, snapshot_rev) {
+                continue;
+            }
+
+            if let Err(e) = apply(event).await {
+                warn!(target: "indexer", "Failed to replay event of {}: {:?}", did, e);
+            }
+        }
+    }
+}
+
+/// Take the events held back for a repo, stopping to hold them back once it is empty
+fn take(did: &str) -> Option<Vec<Kind>> {
+    let mut buffers = BUFFERS.get()?.lock().unwrap();
+    let buffer = buffers.get_mut(did)?;
+    if buffer.is_empty() {
+        buffers.remove(did);
+        return None;
+    }
+
+    Some(std::mem::take(buffer))
+}
+
+/// Whether an event is not part of the snapshot at the given revision yet
+fn is_newer(event: &Kind, snapshot_rev: Option<&str>) -> bool {
+    let (Some(snapshot_rev), Kind::CommitEvent { commit, .. }) = (snapshot_rev, event) else {
+        return true;
+    };
+    let rev = match commit {
+        Commit::CreateOrUpdate { rev, .. } | Commit::Delete { rev, .. } => rev,
+    };
+
+    rev.as_str() > snapshot_rev
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::websocket::events::parse_event;
+
+    fn delete_event(did: &str, rev: &str) -> Kind {
+        parse_event(format!(
+            r#"{{"did":"{}","time_us":1,"kind":"commit","commit":{{"rev":"{}","operation":"delete","collection":"app.bsky.feed.like","rkey":"3l5ddo4mtmd2a"}}}}"#,
+            did, rev
+        ))
+        .unwrap()
+    }
+
+    fn rev(event: &Kind) -> String {
+        match event {
+            Kind::CommitEvent {
+                commit: Commit::CreateOrUpdate { rev, .. } | Commit::Delete { rev, .. },
+                ..
+            } => rev.clone(),
+            _ => panic!("not a commit event"),
+        }
+    }
+
+    pub(crate) fn hold(did: &str) {
+        BUFFERS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .insert(did.to_string(), Vec::new());
+    }
+
+    /// Drain the buffer of a repo, returning the revisions of the applied events
+    pub(crate) fn drain_revs(did: &str, snapshot_rev: Option<&str>) -> Vec<String> {
+        let mut applied = Vec::new();
+        futures::executor::block_on(drain(did, snapshot_rev, |event| {
+            applied.push(rev(&event));
+            async { anyhow::Ok(()) }
+        }));
+        applied
+    }
+
+    #[test]
+    fn replays_only_events_newer_than_the_snapshot() {
+        let did = "did:plc:buffersnapshot";
+        hold(did);
+        for rev in [
+            "3l5ddo4mtmd2a",
+            "3l5ddo4mtmd2c",
+            "3l5ddo4mtmd2b",
+            "3l5ddo4mtmd2d",
+        ] {
+            assert!(intercept(delete_event(did, rev)).is_none());
+        }
+
+        assert_eq!(
+            drain_revs(did, Some("3l5ddo4mtmd2b")),
+            ["3l5ddo4mtmd2c", "3l5ddo4mtmd2d"]
+        );
+
+        // once replayed, events are applied live again
+        assert!(intercept(delete_event(did, "3l5ddo4mtmd2e")).is_some());
+    }
+
+    #[test]
+    fn replays_all_events_without_a_snapshot() {
+        let did = "did:plc:buffernosnapshot";
+        hold(did);
+        for rev in ["3l5ddo4mtmd2a", "3l5ddo4mtmd2b"] {
+            assert!(intercept(delete_event(did, rev)).is_none());
+        }
+
+        assert_eq!(drain_revs(did, None), ["3l5ddo4mtmd2a", "3l5ddo4mtmd2b"]);
+        assert!(intercept(delete_event(did, "3l5ddo4mtmd2c")).is_some());
+    }
+
+    #[test]
+    fn passes_events_of_other_repos_through() {
+        hold("did:plc:bufferheld");
+        assert!(intercept(delete_event("did:plc:bufferother", "3l5ddo4mtmd2a")).is_some());
+        assert!(drain_revs("did:plc:bufferother", None).is_empty());
+    }
+}
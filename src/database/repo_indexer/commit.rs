@@ -0,0 +1,161 @@
+use ipld_core::cid::Cid;
+use serde::{Deserialize, Serialize};
+
+use super::error::RepoError;
+use crate::did::key::PublicKey;
+
+/// Signed commit at the root of a repository
+#[derive(Deserialize, Debug)]
+pub struct Commit {
+    pub did: String,
+    pub version: u8,
+    pub data: Cid,
+    pub rev: String,
+    pub prev: Option<Cid>,
+    #[serde(with = "serde_bytes")]
+    pub sig: Vec<u8>,
+}
+
+impl Commit {
+    /// Decode a commit block and check it belongs to the expected repository
+    pub fn decode(block: &[u8], did: &str) -> Result<Self, RepoError> {
+        let commit: Commit = serde_ipld_dagcbor::from_slice(block)
+            .map_err(|e| RepoError::Corrupt(format!("invalid commit block: {}", e)))?;
+        if commit.version != 2 && commit.version != 3 {
+            return Err(RepoError::Corrupt(format!(
+                "unsupported commit version {}",
+                commit.version
+            )));
+        }
+        if commit.did != did {
+            return Err(RepoError::Corrupt(format!(
+                "commit belongs to {} instead of {}",
+                commit.did, did
+            )));
+        }
+
+        Ok(commit)
+    }
+
+    /// Verify the commit signature against the signing key of the repository
+    pub fn verify(&self, key: &PublicKey) -> Result<(), RepoError> {
+        if !key.verify(&self.unsigned_bytes()?, &self.sig) {
+            return Err(RepoError::InvalidSignature);
+        }
+
+        Ok(())
+    }
+
+    /// Encode the commit without its signature, as it was signed
+    pub fn unsigned_bytes(&self) -> Result<Vec<u8>, RepoError> {
+        let unsigned = UnsignedCommit {
+            did: &self.did,
+            rev: &self.rev,
+            data: &self.data,
+            prev: self.prev.as_ref(),
+            version: self.version,
+        };
+        serde_ipld_dagcbor::to_vec(&unsigned)
+            .map_err(|e| RepoError::Corrupt(format!("failed to encode commit: {}", e)))
+    }
+}
+
+/// Commit without its signature, the fields are in canonical dag-cbor order
+#[derive(Serialize)]
+struct UnsignedCommit<'a> {
+    did: &'a str,
+    rev: &'a str,
+    data: &'a Cid,
+    prev: Option<&'a Cid>,
+    version: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tree root of the signed commit
+    const DATA: &str = "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm";
+    const K256_KEY: &str = "zQ3shjyJXUaRJC2GC43mX8aPrUhoTdoiongXhZjsdTzPKYZUM";
+    const K256_SIG: &str = "00368848162916502075bc071a99f258f839814e33cf1c509155d3ca95514d4d\
+                            5950cf37c73da63866c70622834783350863fec471c9bf0f7ebd350e2e037c4e";
+    const K256_HIGH_S: &str = "00368848162916502075bc071a99f258f839814e33cf1c509155d3ca95514d4d\
+                               a6af30c838c259c79938f9dd7cb87cc9b24ade223d7ee12c4115297ea232c4f3";
+    const P256_KEY: &str = "zDnaex62me84JZnkEzmeYRa8FCLNe7y1asoSwBMK26GBYpL7c";
+    const P256_SIG: &str = "e404e4c6bfa18f7bd7898ec06812c543f48d5a09e7c20144763834d98dec00ba\
+                            5e25d47ede60e8e3a26b10cb098b14ff58f1e0fb46dab266c398ce4101a7c693";
+    const P256_HIGH_S: &str = "e404e4c6bfa18f7bd7898ec06812c543f48d5a09e7c20144763834d98dec00ba\
+                               a1da2b80219f171d5d94ef34f674eb0063f519b2603cec1e3020fc81fabb5ebe";
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn commit(sig: &str) -> Commit {
+        Commit {
+            did: "did:plc:ewvi7nxzyoun6zhxrhs64oiz".to_string(),
+            version: 3,
+            data: DATA.parse().unwrap(),
+            rev: "3l5ddo4mtmd2a".to_string(),
+            prev: None,
+            sig: hex(sig),
+        }
+    }
+
+    fn key(multikey: &str) -> PublicKey {
+        PublicKey::from_verification_method("Multikey", multikey).unwrap()
+    }
+
+    #[test]
+    fn encodes_unsigned_commit_canonically() {
+        let expected = hex(
+            "a56364696478206469643a706c633a65777669376e787a796f756e367a6878726873363\
+             46f697a637265766d336c3564646f346d746d6432616464617461d82a58250001711220\
+             9dfefe61dd76ea3dcae5023880b08379d57adf20482d6fdbe2759289f647677b6470726\
+             576f66776657273696f6e03",
+        );
+        assert_eq!(commit(K256_SIG).unsigned_bytes().unwrap(), expected);
+    }
+
+    #[test]
+    fn verifies_known_signatures() {
+        assert!(commit(K256_SIG).verify(&key(K256_KEY)).is_ok());
+        assert!(commit(P256_SIG).verify(&key(P256_KEY)).is_ok());
+    }
+
+    #[test]
+    fn rejects_high_s_signatures() {
+        assert!(matches!(
+            commit(K256_HIGH_S).verify(&key(K256_KEY)),
+            Err(RepoError::InvalidSignature)
+        ));
+        assert!(matches!(
+            commit(P256_HIGH_S).verify(&key(P256_KEY)),
+            Err(RepoError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn rejects_modified_commits_and_foreign_keys() {
+        let mut modified = commit(K256_SIG);
+        modified.rev = "3l5ddo4mtmd2b".to_string();
+        assert!(matches!(
+            modified.verify(&key(K256_KEY)),
+            Err(RepoError::InvalidSignature)
+        ));
+        assert!(matches!(
+            commit(K256_SIG).verify(&key(P256_KEY)),
+            Err(RepoError::InvalidSignature)
+        ));
+
+        let mut truncated = commit(P256_SIG);
+        truncated.sig.pop();
+        assert!(matches!(
+            truncated.verify(&key(P256_KEY)),
+            Err(RepoError::InvalidSignature)
+        ));
+    }
+}
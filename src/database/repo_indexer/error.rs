@@ -0,0 +1,53 @@
+use std::fmt;
+
+use ipld_core::cid::Cid;
+
+/// Reasons a downloaded repository can not be indexed
+#[derive(Debug)]
+pub enum RepoError {
+    /// A block does not hash to its CID
+    CidMismatch(Cid),
+    /// A block uses a hash function or codec not allowed in repositories
+    UnsupportedCid(Cid),
+    /// The commit or a tree node violates the repository format
+    Corrupt(String),
+    /// The DID document declares no usable signing key
+    SigningKey(String),
+    /// The DID document declares no personal data server
+    NoPds,
+    /// The commit signature does not match the signing key
+    InvalidSignature,
+    /// Blocks referenced by the tree are missing from the CAR file
+    Incomplete { missing: usize },
+    /// The repository needs more cached blocks than allowed
+    CacheExceeded { limit: usize },
+}
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoError::CidMismatch(cid) => {
+                write!(f, "Corrupt repo: block {} does not match its CID", cid)
+            }
+            RepoError::UnsupportedCid(cid) => write!(f, "Corrupt repo: unsupported CID {}", cid),
+            RepoError::Corrupt(reason) => write!(f, "Corrupt repo: {}", reason),
+            RepoError::SigningKey(reason) => write!(f, "Invalid signing key: {}", reason),
+            RepoError::NoPds => write!(f, "DID document declares no #atproto_pds service"),
+            RepoError::InvalidSignature => write!(f, "Invalid commit signature"),
+            RepoError::Incomplete { missing } => {
+                write!(
+                    f,
+                    "Incomplete repo: {} referenced blocks are missing",
+                    missing
+                )
+            }
+            RepoError::CacheExceeded { limit } => write!(
+                f,
+                "Repo exceeds the block cache of {} bytes, raise --repo-block-cache",
+                limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RepoError {}
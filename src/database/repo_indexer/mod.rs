@@ -0,0 +1,501 @@
+use crate::database::handlers::on_commit_event_createorupdate;
+use crate::did::{DidDocument, DidResolver};
+use anyhow::Context;
+use atrium_api::{
+    record::KnownRecord,
+    types::string::{Did, RecordKey},
+};
+use error::RepoError;
+use futures::stream::TryStreamExt;
+use ipld_core::cid::Cid;
+use iroh_car::CarReader;
+use log::{debug, error, info, warn};
+use queue::Job;
+use reqwest::Client;
+use seed::SeedOptions;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::OnceLock};
+use surrealdb::{engine::any::Any, Surreal};
+use tokio::sync::Semaphore;
+use tokio_util::io::StreamReader;
+use walker::{RepoRecord, RepoWalker};
+
+pub mod buffer;
+mod commit;
+mod error;
+mod prune;
+mod queue;
+pub mod seed;
+mod walker;
+
+static STATE: OnceLock<SharedState> = OnceLock::new();
+
+/// Amount of discovered DIDs dispatched at once
+const DISPATCH_BATCH_SIZE: usize = 500;
+/// Delay between two searches for stale repos
+const RECRAWL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// Options for the full repo indexer
+#[derive(Debug)]
+pub struct RepoIndexerOptions {
+    /// Maximum amount of concurrent repo downloads
+    pub max_concurrent_requests: usize,
+    /// Maximum amount of bytes cached per repo for blocks that arrive before their parent
+    pub block_cache_size: usize,
+    /// Resolver for the DIDs of the indexed repos
+    pub resolver: DidResolver,
+    /// Sources the DIDs to index are discovered from
+    pub seed: SeedOptions,
+    /// Re-crawl repos indexed longer ago than this many seconds
+    pub recrawl_after: Option<u64>,
+    /// Re-crawl repos whose revision lags behind the firehose
+    pub recrawl_lagging: bool,
+}
+
+pub async fn start_full_repo_indexer(
+    db: Surreal<Any>,
+    options: RepoIndexerOptions,
+) -> anyhow::Result<()> {
+    STATE.get_or_init(|| SharedState {
+        db,
+        http_client: Client::new(),
+        http_semaphore: Semaphore::new(options.max_concurrent_requests),
+        block_cache_size: options.block_cache_size,
+        resolver: options.resolver,
+    });
+    let state = STATE.get().unwrap();
+
+    info!(target: "indexer", "Starting full repo indexer with at most {} concurrent requests", options.max_concurrent_requests);
+
+    // jobs that were running when the indexer stopped are picked up again
+    queue::reset_running(&state.db).await?;
+    let requeued = queue::requeue_cache_exceeded(&state.db, state.block_cache_size).await?;
+    if requeued > 0 {
+        info!(target: "indexer", "Queued {} repos again that exceeded a smaller block cache", requeued);
+    }
+    seed::spawn(state.db.clone(), options.seed)?;
+
+    if options.recrawl_after.is_some() || options.recrawl_lagging {
+        tokio::spawn(async move {
+            loop {
+                match queue::requeue_stale(
+                    &state.db,
+                    options.recrawl_after,
+                    options.recrawl_lagging,
+                )
+                .await
+                {
+                    Ok(0) => {}
+                    Ok(count) => {
+                        info!(target: "indexer", "Queued {} stale repos for re-crawling", count)
+                    }
+                    Err(e) => error!(target: "indexer", "Failed to queue stale repos: {:?}", e),
+                }
+                tokio::time::sleep(RECRAWL_INTERVAL).await;
+            }
+        });
+    }
+
+    loop {
+        let jobs = queue::claim(&state.db, DISPATCH_BATCH_SIZE).await?;
+
+        if jobs.is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(10000)).await;
+            continue;
+        }
+
+        for job in jobs {
+            tokio::spawn(async move {
+                let res = task_handler(job).await;
+                if let Err(e) = res {
+                    error!(target: "indexer", "Handler task failed: {:?}", e);
+                } else {
+                    debug!(target: "indexer", "Handler task exited");
+                }
+            });
+        }
+
+        while state.http_semaphore.available_permits() < 50 {
+            info!(target: "indexer", "Stalling for permits!");
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SharedState {
+    db: Surreal<Any>,
+    http_client: Client,
+    http_semaphore: Semaphore,
+    block_cache_size: usize,
+    resolver: DidResolver,
+}
+
+async fn task_handler(job: Job) -> anyhow::Result<()> {
+    let state = STATE.get().unwrap();
+
+    // live events arriving during the download are applied on top of the snapshot
+    let permit = state.http_semaphore.acquire().await.unwrap();
+    let buffer = buffer::start(&state.db, &job.did);
+    let res = index_repo(state, &job.did).await;
+    drop(permit);
+    // errors are handled after the replay, so no live event is lost on a failed backfill
+    buffer.replay(res.as_deref().ok()).await;
+
+    if let Err(e) = res {
+        let e_str = format!("{}", e);
+        // repos that are broken on the server are recorded instead of retried
+        if e_str == "Failed to parse CAR file: early eof" || e.is::<RepoError>() {
+            let did_key = crate::database::utils::did_to_key(job.did.as_str())?;
+            let timestamp_us = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_micros();
+            let _: Option<super::definitions::Record> = state
+                .db
+                .upsert(("li_did", did_key))
+                .content(LastIndexedTimestamp {
+                    time_us: timestamp_us as u64,
+                    time_dt: chrono::Utc::now().into(),
+                    error: Some(e_str.clone()),
+                    rev: None,
+                })
+                .await?;
+            match e.downcast_ref::<RepoError>() {
+                Some(RepoError::CacheExceeded { limit }) => {
+                    queue::fail_cache_exceeded(&state.db, &job, &e_str, *limit).await?
+                }
+                _ => queue::fail(&state.db, &job, &e_str).await?,
+            }
+        } else {
+            warn!(target: "indexer", "Failed to index repo {}: {}", job.did, e);
+            queue::retry(&state.db, &job, &e_str).await?;
+        }
+    } else {
+        queue::complete(&state.db, &job).await?;
+    }
+    Ok(())
+}
+
+/// Index the repo of a DID, returning the revision it was indexed at
+async fn index_repo(state: &SharedState, did: &String) -> anyhow::Result<String> {
+    let did_key = crate::database::utils::did_to_key(did.as_str())?;
+    let previous: Option<LastIndexedTimestamp> = state.db.select(("li_did", &did_key)).await?;
+    let timestamp_us = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_micros();
+
+    let document = state.resolver.resolve(did).await?;
+    let res = index_snapshot(
+        state,
+        did,
+        &did_key,
+        &document,
+        previous.as_ref(),
+        timestamp_us,
+    )
+    .await;
+    let Err(e) = res else {
+        return res;
+    };
+    if !stale_document(&e) {
+        return Err(e);
+    }
+
+    // the signing key or PDS may have changed since the document was cached
+    debug!(target: "indexer", "Resolving {} again after: {}", did, e);
+    state.resolver.invalidate(did).await?;
+    let document = state.resolver.resolve(did).await?;
+    index_snapshot(
+        state,
+        did,
+        &did_key,
+        &document,
+        previous.as_ref(),
+        timestamp_us,
+    )
+    .await
+}
+
+/// Whether indexing a repo may succeed with a freshly resolved DID document
+fn stale_document(e: &anyhow::Error) -> bool {
+    if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+        // the repo moved to another PDS or the old one is gone
+        return e.is_connect() || e.status().is_some_and(|s| s.is_client_error());
+    }
+
+    matches!(
+        e.downcast_ref::<RepoError>(),
+        Some(RepoError::InvalidSignature)
+    )
+}
+
+/// Download and index the current snapshot of a repo from the PDS in its DID document
+async fn index_snapshot(
+    state: &SharedState,
+    did: &String,
+    did_key: &str,
+    document: &DidDocument,
+    previous: Option<&LastIndexedTimestamp>,
+    timestamp_us: u128,
+) -> anyhow::Result<String> {
+    debug!(target: "indexer", "Indexing repo {} ({})", did, document.handles().join(", "));
+    let signing_key = document
+        .signing_key()
+        .map_err(|e| RepoError::SigningKey(format!("{:#}", e)))?;
+
+    let pds = document.pds_endpoint().ok_or(RepoError::NoPds)?;
+
+    // skip repos that did not change since they were last indexed
+    if let Some(rev) = unchanged_rev(&state.http_client, pds, did, previous).await {
+        debug!(target: "indexer", "Repo {} is unchanged at {}", did, rev);
+        let _: Option<super::definitions::Record> = state
+            .db
+            .upsert(("li_did", did_key))
+            .content(LastIndexedTimestamp {
+                time_us: timestamp_us as u64,
+                time_dt: chrono::Utc::now().into(),
+                error: None,
+                rev: Some(rev.clone()),
+            })
+            .await?;
+        return Ok(rev);
+    }
+
+    let car_res = state
+        .http_client
+        .get(format!("{}/xrpc/com.atproto.sync.getRepo?did={}", pds, did,))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    // walk the repository while it is being downloaded
+    let stream = car_res.bytes_stream().map_err(std::io::Error::other);
+    let mut car_reader = CarReader::new(StreamReader::new(stream)).await?;
+    let root = *car_reader
+        .header()
+        .roots()
+        .first()
+        .context("CAR file has no root")?;
+
+    let mut walker = RepoWalker::new(root, did, signing_key, state.block_cache_size);
+    let mut present = HashSet::new();
+    while let Some((cid, block)) = car_reader.next_block().await? {
+        for record in walker.push(cid, block)? {
+            present.insert(record.key.clone());
+            index_record(state, did, record).await;
+        }
+    }
+    let commit = walker.finish()?;
+
+    // records deleted while they weren't followed live are missing from the snapshot
+    let pruned = prune::prune(&state.db, did, did_key, &commit.rev, &present).await?;
+    if pruned > 0 {
+        debug!(target: "indexer", "Deleted {} records no longer in repo {}", pruned, did);
+    }
+
+    let _: Option<super::definitions::Record> = state
+        .db
+        .upsert(("li_did", did_key))
+        .content(LastIndexedTimestamp {
+            time_us: timestamp_us as u64,
+            time_dt: chrono::Utc::now().into(),
+            error: None,
+            rev: Some(commit.rev.clone()),
+        })
+        .await?;
+    Ok(commit.rev)
+}
+
+/// Revision of a repo if it did not change since it was last indexed completely
+///
+/// Repos whose latest commit can't be fetched are downloaded again.
+async fn unchanged_rev(
+    http_client: &Client,
+    pds: &str,
+    did: &str,
+    previous: Option<&LastIndexedTimestamp>,
+) -> Option<String> {
+    let rev = previous
+        .filter(|li| li.error.is_none())
+        .and_then(|li| li.rev.as_ref())?;
+
+    let latest = match fetch_latest_commit(http_client, pds, did).await {
+        Ok(latest) => latest,
+        Err(e) => {
+            warn!(target: "indexer", "Failed to fetch the latest commit of {}, downloading the repo: {}", did, e);
+            return None;
+        }
+    };
+
+    (latest.rev == *rev).then_some(latest.rev)
+}
+
+/// Fetch the latest commit of a repo from its PDS
+async fn fetch_latest_commit(
+    http_client: &Client,
+    pds: &str,
+    did: &str,
+) -> anyhow::Result<LatestCommit> {
+    Ok(http_client
+        .get(format!(
+            "{}/xrpc/com.atproto.sync.getLatestCommit?did={}",
+            pds, did
+        ))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<LatestCommit>()
+        .await?)
+}
+
+/// Write a single record found in a repository to the database
+async fn index_record(state: &SharedState, did: &String, record: RepoRecord) {
+    let Ok(known_record) = serde_ipld_dagcbor::from_slice::<KnownRecord>(&record.data) else {
+        return;
+    };
+    let Some((collection, rkey)) = record.key.split_once('/') else {
+        warn!(target: "indexer", "Invalid record key {} in repo {}", record.key, did);
+        return;
+    };
+    let Ok(rkey) = RecordKey::new(rkey.to_string()) else {
+        warn!(target: "indexer", "Invalid record key {} in repo {}", record.key, did);
+        return;
+    };
+
+    let res = on_commit_event_createorupdate(
+        &state.db,
+        Did::new(did.clone()).unwrap(),
+        collection.to_string(),
+        rkey,
+        known_record,
+    )
+    .await;
+    if let Err(e) = res {
+        warn!("on_commit_event_createorupdate {} {}", e, did);
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TreeEntry {
+    pub p: u8,
+    #[serde(with = "serde_bytes")]
+    pub k: Vec<u8>,
+    pub v: Cid,
+    pub t: Option<Cid>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NodeData {
+    pub l: Option<Cid>,
+    pub e: Vec<TreeEntry>,
+}
+
+/// Database struct for a repo indexing timestamp
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LastIndexedTimestamp {
+    pub time_us: u64,
+    pub time_dt: surrealdb::Datetime,
+    pub error: Option<String>,
+    pub rev: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LatestCommit {
+    rev: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    /// Serve a single getLatestCommit response, returning the address of the server
+    fn latest_commit_server(rev: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..read]);
+            }
+            assert!(request.starts_with(
+                b"GET /xrpc/com.atproto.sync.getLatestCommit?did=did:plc:ewvi7nxzyoun6zhxrhs64oiz "
+            ));
+
+            let body = format!(
+                "{{\"cid\":\"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm\",\"rev\":\"{}\"}}",
+                rev
+            );
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+        address
+    }
+
+    fn indexed(rev: Option<&str>, error: Option<&str>) -> LastIndexedTimestamp {
+        LastIndexedTimestamp {
+            time_us: 0,
+            time_dt: chrono::Utc::now().into(),
+            error: error.map(str::to_string),
+            rev: rev.map(str::to_string),
+        }
+    }
+
+    fn unchanged(pds: &str, previous: Option<&LastIndexedTimestamp>) -> Option<String> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(unchanged_rev(
+            &Client::new(),
+            pds,
+            "did:plc:ewvi7nxzyoun6zhxrhs64oiz",
+            previous,
+        ))
+    }
+
+    #[test]
+    fn skips_repos_at_the_latest_rev() {
+        let pds = latest_commit_server("3l5ddo4mtmd2a");
+        let previous = indexed(Some("3l5ddo4mtmd2a"), None);
+        assert_eq!(
+            unchanged(&pds, Some(&previous)).as_deref(),
+            Some("3l5ddo4mtmd2a")
+        );
+    }
+
+    #[test]
+    fn downloads_changed_repos() {
+        let pds = latest_commit_server("3l5ddo4mtmd2b");
+        let previous = indexed(Some("3l5ddo4mtmd2a"), None);
+        assert_eq!(unchanged(&pds, Some(&previous)), None);
+    }
+
+    #[test]
+    fn downloads_repos_without_a_complete_index() {
+        // no request is made, so the address is never connected to
+        let pds = "http://127.0.0.1:9";
+        assert_eq!(unchanged(pds, None), None);
+        assert_eq!(unchanged(pds, Some(&indexed(None, None))), None);
+        let failed = indexed(Some("3l5ddo4mtmd2a"), Some("Invalid commit signature"));
+        assert_eq!(unchanged(pds, Some(&failed)), None);
+    }
+
+    #[test]
+    fn downloads_repos_if_the_latest_commit_is_unavailable() {
+        // nothing listens on the discard port
+        let pds = "http://127.0.0.1:9";
+        let previous = indexed(Some("3l5ddo4mtmd2a"), None);
+        assert_eq!(unchanged(pds, Some(&previous)), None);
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::Result;
+use atrium_api::types::string::{Did, RecordKey};
+use std::collections::HashSet;
+use surrealdb::{engine::any::Any, Surreal};
+
+use crate::database::{
+    collections::{Owner, COLLECTIONS},
+    handlers::on_commit_event_delete,
+};
+
+/// Delete all records of a DID that are missing from a snapshot of its repository
+///
+/// `present` holds the keys (collection/rkey) of every record in the snapshot.
+/// Returns the amount of deleted records.
+pub async fn prune(
+    db: &Surreal<Any>,
+    did: &str,
+    did_key: &str,
+    rev: &str,
+    present: &HashSet<String>,
+) -> Result<usize> {
+    let mut deleted = 0;
+    for collection in COLLECTIONS {
+        let query = match collection.owner {
+            Owner::Did => {
+                // clearing a profile that doesn't exist is a no-op
+                if !present.contains(&format!("{}/self", collection.nsid)) {
+                    delete(db, did, did_key, rev, collection.nsid, "self").await?;
+                }
+                continue;
+            }
+            Owner::Author => format!(
+                "SELECT VALUE record::id(id) FROM {} WHERE author = did:{};",
+                collection.table, did_key
+            ),
+            Owner::Relation => format!(
+                "SELECT VALUE record::id(id) FROM did:{}->{};",
+                did_key, collection.table
+            ),
+        };
+        let keys: Vec<String> = db.query(query).await?.take(0)?;
+
+        for rkey in stale_rkeys(collection.nsid, did_key, &keys, present) {
+            delete(db, did, did_key, rev, collection.nsid, rkey).await?;
+            deleted += 1;
+        }
+    }
+
+    Ok(deleted)
+}
+
+/// Record keys of stored record ids ({rkey}_{did key}) that are missing from the snapshot
+pub(super) fn stale_rkeys<'a>(
+    nsid: &str,
+    did_key: &str,
+    ids: &'a [String],
+    present: &HashSet<String>,
+) -> Vec<&'a str> {
+    let suffix = format!("_{}", did_key);
+    ids.iter()
+        .filter_map(|id| id.strip_suffix(&suffix))
+        .filter(|rkey| !present.contains(&format!("{}/{}", nsid, rkey)))
+        .collect()
+}
+
+/// Delete a single record the same way a delete commit would
+async fn delete(
+    db: &Surreal<Any>,
+    did: &str,
+    did_key: &str,
+    rev: &str,
+    nsid: &str,
+    rkey: &str,
+) -> Result<()> {
+    on_commit_event_delete(
+        db,
+        Did::new(did.to_string()).map_err(anyhow::Error::msg)?,
+        0,
+        did_key.to_string(),
+        rev.to_string(),
+        nsid.to_string(),
+        RecordKey::new(rkey.to_string()).map_err(anyhow::Error::msg)?,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::repo_indexer::buffer::{
+            intercept,
+            tests::{drain_revs, hold},
+        },
+        websocket::events::parse_event,
+    };
+
+    #[test]
+    fn finds_only_records_missing_from_the_snapshot() {
+        let ids = [
+            "3l5ddo4mtmd2a_plc_abc".to_string(),
+            "3l5ddo4mtmd2b_plc_abc".to_string(),
+            "3l5ddo4mtmd2c_plc_abc".to_string(),
+            // ids of other repos are never touched
+            "3l5ddo4mtmd2d_plc_abcd".to_string(),
+        ];
+        let present = HashSet::from([
+            "app.bsky.feed.like/3l5ddo4mtmd2a".to_string(),
+            "app.bsky.feed.post/3l5ddo4mtmd2b".to_string(),
+        ]);
+
+        assert_eq!(
+            stale_rkeys("app.bsky.feed.like", "plc_abc", &ids, &present),
+            ["3l5ddo4mtmd2b", "3l5ddo4mtmd2c"]
+        );
+        assert!(stale_rkeys("app.bsky.feed.like", "plc_abc", &[], &present).is_empty());
+    }
+
+    #[test]
+    fn records_created_live_survive_the_prune() {
+        // like "a" is in the snapshot at rev b, "b" was deleted while the repo wasn't
+        // followed and "c" was created live during the download
+        let did = "did:plc:bufferprune";
+        hold(did);
+        let create = parse_event(format!(
+            r#"{{"did":"{}","time_us":1,"kind":"commit","commit":{{"rev":"3l5ddo4mtmd2c","operation":"create","collection":"app.bsky.feed.like","rkey":"3l5ddo4mtmd2c","cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm","record":{{"$type":"app.bsky.feed.like","subject":{{"uri":"at://did:plc:bufferprune/app.bsky.feed.post/3l5ddo4mtmd2a","cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"}},"createdAt":"2024-10-01T12:00:00.000Z"}}}}}}"#,
+            did
+        ))
+        .unwrap();
+        assert!(intercept(create).is_none());
+
+        // the held back record isn't stored yet when the snapshot is pruned
+        let stored = [
+            "3l5ddo4mtmd2a_plc_bufferprune".to_string(),
+            "3l5ddo4mtmd2b_plc_bufferprune".to_string(),
+        ];
+        let present = HashSet::from(["app.bsky.feed.like/3l5ddo4mtmd2a".to_string()]);
+        assert_eq!(
+            stale_rkeys("app.bsky.feed.like", "plc_bufferprune", &stored, &present),
+            ["3l5ddo4mtmd2b"]
+        );
+
+        // and is applied on top of the snapshot afterwards
+        assert_eq!(drain_revs(did, Some("3l5ddo4mtmd2b")), ["3l5ddo4mtmd2c"]);
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::time::Duration;
+use surrealdb::{engine::any::Any, RecordId, Surreal};
+
+/// Attempts after which a job is given up on
+const MAX_ATTEMPTS: u32 = 10;
+/// Delay before the first retry, doubled with every further attempt
+const RETRY_BASE: Duration = Duration::from_secs(60);
+/// Amount of stale repos queued for re-crawling at once
+const RECRAWL_BATCH_SIZE: usize = 10000;
+/// Upper bound for the delay between two attempts
+const RETRY_MAX: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A repo waiting to be indexed
+#[derive(Debug, Deserialize)]
+pub struct Job {
+    pub id: RecordId,
+    pub did: String,
+    pub attempts: u32,
+}
+
+/// Return jobs left running by a previous run to the queue
+pub async fn reset_running(db: &Surreal<Any>) -> Result<()> {
+    db.query("UPDATE backfill_did SET status = 'pending' WHERE status = 'running';")
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+/// Return jobs that failed with a block cache smaller than `cache_limit` to the queue
+pub async fn requeue_cache_exceeded(db: &Surreal<Any>, cache_limit: usize) -> Result<usize> {
+    let mut res = db
+        .query(
+            "LET $requeued = (SELECT VALUE id FROM backfill_did WHERE status = 'failed' AND cacheLimit != NONE AND cacheLimit < $limit); \
+            UPDATE $requeued SET status = 'pending', attempts = 0, nextRetry = time::now(), cacheLimit = NONE RETURN NONE; \
+            RETURN array::len($requeued);",
+        )
+        .bind(("limit", cache_limit))
+        .await?
+        .check()?;
+    let count: Option<usize> = res.take(2)?;
+
+    Ok(count.unwrap_or(0))
+}
+
+/// Take up to `limit` jobs that are due off the queue
+pub async fn claim(db: &Surreal<Any>, limit: usize) -> Result<Vec<Job>> {
+    let mut res = db
+        .query(format!(
+            "SELECT id, did, attempts FROM backfill_did WHERE status = 'pending' AND nextRetry <= time::now() LIMIT {};",
+            limit
+        ))
+        .await?;
+    let jobs: Vec<Job> = res.take(0)?;
+
+    if !jobs.is_empty() {
+        let ids: Vec<RecordId> = jobs.iter().map(|job| job.id.clone()).collect();
+        db.query("UPDATE $ids SET status = 'running';")
+            .bind(("ids", ids))
+            .await?
+            .check()?;
+    }
+
+    Ok(jobs)
+}
+
+/// Mark a job as done
+pub async fn complete(db: &Surreal<Any>, job: &Job) -> Result<()> {
+    db.query("UPDATE $id SET status = 'done', lastError = NONE;")
+        .bind(("id", job.id.clone()))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+/// Give up on a job
+pub async fn fail(db: &Surreal<Any>, job: &Job, error: &str) -> Result<()> {
+    db.query("UPDATE $id SET status = 'failed', attempts += 1, lastError = $error;")
+        .bind(("id", job.id.clone()))
+        .bind(("error", error.to_string()))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+/// Give up on a job that exceeded the block cache, until the cache is larger than `cache_limit`
+pub async fn fail_cache_exceeded(
+    db: &Surreal<Any>,
+    job: &Job,
+    error: &str,
+    cache_limit: usize,
+) -> Result<()> {
+    db.query(
+        "UPDATE $id SET status = 'failed', attempts += 1, lastError = $error, cacheLimit = $limit;",
+    )
+    .bind(("id", job.id.clone()))
+    .bind(("error", error.to_string()))
+    .bind(("limit", cache_limit))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Schedule another attempt of a job, giving up after too many attempts
+pub async fn retry(db: &Surreal<Any>, job: &Job, error: &str) -> Result<()> {
+    let attempts = job.attempts + 1;
+    if attempts >= MAX_ATTEMPTS {
+        return fail(db, job, error).await;
+    }
+
+    let delay = retry_delay(attempts);
+    db.query(format!(
+        "UPDATE $id SET status = 'pending', attempts += 1, nextRetry = time::now() + {}s, lastError = $error;",
+        delay.as_secs()
+    ))
+    .bind(("id", job.id.clone()))
+    .bind(("error", error.to_string()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Delay before the next attempt of a job that failed `attempts` times
+fn retry_delay(attempts: u32) -> Duration {
+    let doublings = attempts.saturating_sub(1).min(16);
+    RETRY_BASE.saturating_mul(1 << doublings).min(RETRY_MAX)
+}
+
+/// Queue indexed repos again that are older than `max_age` seconds or, if `lagging`
+/// is set, behind the revision seen on the firehose
+pub async fn requeue_stale(
+    db: &Surreal<Any>,
+    max_age: Option<u64>,
+    lagging: bool,
+) -> Result<usize> {
+    let mut conditions = Vec::new();
+    if let Some(max_age) = max_age {
+        conditions.push(format!("time_dt < time::now() - {}s", max_age));
+    }
+    if lagging {
+        conditions.push(
+            "rev < (SELECT VALUE rev FROM ONLY type::thing('repo_rev', record::id($parent.id)))"
+                .to_string(),
+        );
+    }
+    if conditions.is_empty() {
+        return Ok(0);
+    }
+
+    let mut res = db
+        .query(format!(
+            "LET $stale = (SELECT VALUE type::thing('backfill_did', record::id(id)) FROM li_did \
+                WHERE error = NONE AND type::thing('backfill_did', record::id(id)).status = 'done' AND ({}) LIMIT {}); \
+            UPDATE $stale SET status = 'pending', attempts = 0, nextRetry = time::now() RETURN NONE; \
+            RETURN array::len($stale);",
+            conditions.join(" OR "),
+            RECRAWL_BATCH_SIZE
+        ))
+        .await?
+        .check()?;
+    let count: Option<usize> = res.take(2)?;
+
+    Ok(count.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_from_the_base() {
+        assert_eq!(retry_delay(1), RETRY_BASE);
+        assert_eq!(retry_delay(2), RETRY_BASE * 2);
+        assert_eq!(retry_delay(3), RETRY_BASE * 4);
+        assert_eq!(retry_delay(MAX_ATTEMPTS - 1), RETRY_BASE * 256);
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        assert_eq!(retry_delay(0), RETRY_BASE);
+        assert_eq!(retry_delay(12), RETRY_MAX);
+        assert_eq!(retry_delay(u32::MAX), RETRY_MAX);
+    }
+}
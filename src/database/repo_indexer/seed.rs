@@ -0,0 +1,367 @@
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::{future::Future, time::Duration};
+use surrealdb::{engine::any::Any, Datetime, RecordId, Surreal};
+
+use crate::{
+    config::BackfillSeed,
+    database::{
+        definitions::Record,
+        utils::{did_to_key, unsafe_user_key_to_did},
+    },
+};
+
+/// Amount of DIDs requested or read at once
+const PAGE_SIZE: usize = 1000;
+/// Delay before polling a live seed source for new DIDs again
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Delay before retrying a failed step of a seed source, doubled with every further failure
+const RETRY_BASE: Duration = Duration::from_secs(5);
+/// Upper bound for the delay between two attempts of a step
+const RETRY_MAX: Duration = Duration::from_secs(10 * 60);
+
+/// Options for the sources DIDs to backfill are discovered from
+#[derive(Debug)]
+pub struct SeedOptions {
+    /// Enabled seed sources
+    pub sources: Vec<BackfillSeed>,
+    /// File with one DID per line for the file source
+    pub file: Option<String>,
+    /// Personal data servers to enumerate for the list-repos source
+    pub pds: Vec<String>,
+    /// PLC directory to read the export of for the plc-export source
+    pub plc_directory: String,
+}
+
+/// Database struct for a discovered DID
+#[derive(Debug, Serialize)]
+struct BackfillDid {
+    id: RecordId,
+    did: String,
+    source: &'static str,
+    #[serde(rename = "discoveredAt")]
+    discovered_at: Datetime,
+}
+
+/// Database struct for the progress of a seed source
+#[derive(Debug, Serialize, Deserialize)]
+struct SeedCursor {
+    cursor: String,
+    #[serde(rename = "updatedAt")]
+    updated_at: Datetime,
+}
+
+#[derive(Deserialize)]
+struct BskyFollowRes {
+    #[serde(rename = "in")]
+    pub from: RecordId,
+    #[serde(rename = "out")]
+    pub to: RecordId,
+
+    pub id: RecordId,
+}
+
+#[derive(Deserialize)]
+struct ListReposResponse {
+    cursor: Option<String>,
+    repos: Vec<ListReposRepo>,
+}
+
+#[derive(Deserialize)]
+struct ListReposRepo {
+    did: String,
+}
+
+#[derive(Deserialize)]
+struct PlcExportEntry {
+    did: String,
+    #[serde(rename = "createdAt")]
+    created_at: String,
+}
+
+/// Start all enabled seed sources in the background
+pub fn spawn(db: Surreal<Any>, options: SeedOptions) -> Result<()> {
+    if options.sources.contains(&BackfillSeed::File) && options.file.is_none() {
+        anyhow::bail!("The file backfill seed requires --seed-file");
+    }
+    if options.sources.contains(&BackfillSeed::ListRepos) && options.pds.is_empty() {
+        anyhow::bail!("The list-repos backfill seed requires at least one --seed-pds");
+    }
+
+    let client = Client::new();
+    for source in &options.sources {
+        match source {
+            BackfillSeed::Follows => {
+                let db = db.clone();
+                tokio::spawn(async move { log_exit("follows", follows(&db).await) });
+            }
+            BackfillSeed::File => {
+                let db = db.clone();
+                let path = options.file.clone().unwrap();
+                tokio::spawn(async move { log_exit("file", file(&db, &path).await) });
+            }
+            BackfillSeed::ListRepos => {
+                for pds in &options.pds {
+                    let (db, client) = (db.clone(), client.clone());
+                    let pds = pds.trim_end_matches('/').to_string();
+                    tokio::spawn(async move {
+                        log_exit("list-repos", list_repos(&db, &client, &pds).await)
+                    });
+                }
+            }
+            BackfillSeed::PlcExport => {
+                let (db, client) = (db.clone(), client.clone());
+                let plc_directory = options.plc_directory.trim_end_matches('/').to_string();
+                tokio::spawn(async move {
+                    log_exit("plc-export", plc_export(&db, &client, &plc_directory).await)
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Log the outcome of a seed source
+fn log_exit(source: &str, res: Result<()>) {
+    match res {
+        Ok(()) => info!(target: "indexer", "Backfill seed {} exhausted", source),
+        Err(e) => error!(target: "indexer", "Backfill seed {} failed: {:?}", source, e),
+    }
+}
+
+/// Discover DIDs from the follow graph, polling for new follows forever
+async fn follows(db: &Surreal<Any>) -> Result<()> {
+    let mut anchor = retry("follows", || fetch_seed_cursor(db, "follows")).await;
+    loop {
+        let follows = retry("follows", || fetch_follows(db, anchor.as_deref())).await;
+
+        // the anchor itself is part of every page
+        if follows.len() <= anchor.is_some() as usize {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            continue;
+        }
+
+        let dids: Vec<String> = follows
+            .iter()
+            .flat_map(|follow| [&follow.from, &follow.to])
+            .map(|record_id| unsafe_user_key_to_did(&record_id.key().to_string()))
+            .collect();
+        retry("follows", || discover(db, dids.clone(), "follows")).await;
+
+        let next = follows.last().unwrap().id.key().to_string();
+        retry("follows", || write_seed_cursor(db, "follows", &next)).await;
+        anchor = Some(next);
+    }
+}
+
+/// Fetch a page of follows, starting at the anchor
+async fn fetch_follows(db: &Surreal<Any>, anchor: Option<&str>) -> Result<Vec<BskyFollowRes>> {
+    let query = match anchor {
+        Some(anchor) => format!(
+            "SELECT id,in,out FROM follow:{}.. LIMIT {};",
+            anchor, PAGE_SIZE
+        ),
+        None => format!("SELECT id,in,out FROM follow LIMIT {};", PAGE_SIZE),
+    };
+
+    Ok(db.query(query).await?.take(0)?)
+}
+
+/// Discover DIDs from a file with one DID per line
+async fn file(db: &Surreal<Any>, path: &str) -> Result<()> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read seed file {}", path))?;
+    let lines: Vec<&str> = content.lines().collect();
+
+    let mut position = match retry("file", || fetch_seed_cursor(db, "file")).await {
+        Some(cursor) => cursor.parse::<usize>()?.min(lines.len()),
+        None => 0,
+    };
+    while position < lines.len() {
+        let end = (position + PAGE_SIZE).min(lines.len());
+        let dids: Vec<String> = lines[position..end]
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        retry("file", || discover(db, dids.clone(), "file")).await;
+
+        position = end;
+        let cursor = position.to_string();
+        retry("file", || write_seed_cursor(db, "file", &cursor)).await;
+    }
+
+    Ok(())
+}
+
+/// Discover DIDs by enumerating all repositories hosted on a PDS
+async fn list_repos(db: &Surreal<Any>, client: &Client, pds: &str) -> Result<()> {
+    let name = format!("list-repos {}", pds);
+    let mut cursor = retry(&name, || fetch_seed_cursor(db, &name)).await;
+    loop {
+        let page = retry(&name, || fetch_repos_page(client, pds, cursor.as_deref())).await;
+
+        let exhausted = page.repos.is_empty();
+        let dids: Vec<String> = page.repos.into_iter().map(|repo| repo.did).collect();
+        retry(&name, || discover(db, dids.clone(), "list-repos")).await;
+
+        match page.cursor {
+            Some(next) if !exhausted => {
+                retry(&name, || write_seed_cursor(db, &name, &next)).await;
+                cursor = Some(next);
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Fetch a page of the repositories hosted on a PDS
+async fn fetch_repos_page(
+    client: &Client,
+    pds: &str,
+    cursor: Option<&str>,
+) -> Result<ListReposResponse> {
+    let mut req = client
+        .get(format!("{}/xrpc/com.atproto.sync.listRepos", pds))
+        .query(&[("limit", PAGE_SIZE.to_string())]);
+    if let Some(cursor) = cursor {
+        req = req.query(&[("cursor", cursor)]);
+    }
+
+    Ok(req
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<ListReposResponse>()
+        .await?)
+}
+
+/// Discover DIDs from the operation log of the PLC directory, following it forever
+async fn plc_export(db: &Surreal<Any>, client: &Client, plc_directory: &str) -> Result<()> {
+    let mut after = retry("plc-export", || fetch_seed_cursor(db, "plc-export")).await;
+    loop {
+        let entries = retry("plc-export", || {
+            fetch_plc_export(client, plc_directory, after.as_deref())
+        })
+        .await;
+
+        if let Some(last) = entries.last() {
+            let next = last.created_at.clone();
+            let caught_up = entries.len() < PAGE_SIZE;
+            let dids: Vec<String> = entries.into_iter().map(|entry| entry.did).collect();
+            retry("plc-export", || discover(db, dids.clone(), "plc-export")).await;
+            retry("plc-export", || write_seed_cursor(db, "plc-export", &next)).await;
+            after = Some(next);
+            if !caught_up {
+                continue;
+            }
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Fetch a page of the PLC directory export, skipping entries that can't be parsed
+async fn fetch_plc_export(
+    client: &Client,
+    plc_directory: &str,
+    after: Option<&str>,
+) -> Result<Vec<PlcExportEntry>> {
+    let mut req = client
+        .get(format!("{}/export", plc_directory))
+        .query(&[("count", PAGE_SIZE.to_string())]);
+    if let Some(after) = after {
+        req = req.query(&[("after", after)]);
+    }
+    let body = req.send().await?.error_for_status()?.text().await?;
+
+    let mut entries = Vec::new();
+    for line in body.lines().filter(|line| !line.is_empty()) {
+        let mut bytes = line.as_bytes().to_vec();
+        match simd_json::serde::from_slice::<PlcExportEntry>(&mut bytes) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => debug!(target: "indexer", "Skipping PLC export entry {}: {}", line, e),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Run a step of a seed source until it succeeds, backing off exponentially
+/// after every failure
+async fn retry<T, F, Fut>(source: &str, mut step: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut delay = RETRY_BASE;
+    loop {
+        match step().await {
+            Ok(value) => return value,
+            Err(e) => {
+                warn!(target: "indexer", "Backfill seed {} failed, retrying in {}s: {:?}",
+                    source, delay.as_secs(), e);
+                tokio::time::sleep(delay).await;
+                delay = delay.saturating_mul(2).min(RETRY_MAX);
+            }
+        }
+    }
+}
+
+/// Record newly discovered DIDs, ignoring ones that are already known
+async fn discover(
+    db: &Surreal<Any>,
+    dids: impl IntoIterator<Item = String>,
+    source: &'static str,
+) -> Result<()> {
+    let discovered_at: Datetime = chrono::Utc::now().into();
+    let rows: Vec<BackfillDid> = dids
+        .into_iter()
+        .filter_map(|did| match did_to_key(&did) {
+            Ok(key) => Some(BackfillDid {
+                id: RecordId::from_table_key("backfill_did", key),
+                did,
+                source,
+                discovered_at: discovered_at.clone(),
+            }),
+            Err(e) => {
+                debug!(target: "indexer", "Skipping seed {}: {}", did, e);
+                None
+            }
+        })
+        .collect();
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    db.query("INSERT IGNORE INTO backfill_did $rows;")
+        .bind(("rows", rows))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+/// Fetch the progress of a seed source
+async fn fetch_seed_cursor(db: &Surreal<Any>, name: &str) -> Result<Option<String>> {
+    let res: Option<SeedCursor> = db.select(("seed_cursor", name)).await?;
+
+    Ok(res.map(|c| c.cursor))
+}
+
+/// Write the progress of a seed source
+async fn write_seed_cursor(db: &Surreal<Any>, name: &str, cursor: &str) -> Result<()> {
+    let _: Option<Record> = db
+        .upsert(("seed_cursor", name))
+        .content(SeedCursor {
+            cursor: cursor.to_string(),
+            updated_at: chrono::Utc::now().into(),
+        })
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,593 @@
+use ipld_core::cid::Cid;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::{commit::Commit, error::RepoError, NodeData};
+use crate::did::key::PublicKey;
+
+/// Multicodec code of dag-cbor
+const DAG_CBOR: u64 = 0x71;
+/// Multihash code of sha2-256
+const SHA2_256: u64 = 0x12;
+
+/// Role of a block referenced by the part of the repository walked so far
+#[derive(Debug)]
+enum Want {
+    /// The commit at the root of the repository
+    Commit,
+    /// A node of the merkle search tree
+    Node(NodeBounds),
+    /// A record stored under the given key
+    Record(String),
+}
+
+/// Constraints a tree node inherits from its parent
+#[derive(Debug, Clone, Default)]
+struct NodeBounds {
+    /// Layer of the parent node, the node must be below it
+    parent_layer: Option<u32>,
+    /// All keys in the node must be greater than this key
+    lower: Option<Vec<u8>>,
+    /// All keys in the node must be less than this key
+    upper: Option<Vec<u8>>,
+}
+
+/// A record found while walking a repository
+#[derive(Debug)]
+pub struct RepoRecord {
+    /// Key of the record (collection/rkey)
+    pub key: String,
+    /// Encoded record
+    pub data: Vec<u8>,
+}
+
+/// Walks the merkle search tree of a repository as its blocks arrive
+///
+/// Every block is checked against its CID and no record is returned before
+/// the commit signature was verified. Blocks that arrive before the
+/// block referencing them are cached until needed, up to a fixed amount of bytes.
+/// The most recent record blocks are kept within the same limit, so records
+/// stored under several keys can be returned for each of them.
+pub struct RepoWalker {
+    did: String,
+    signing_key: PublicKey,
+    commit: Option<Commit>,
+    wanted: HashMap<Cid, Vec<Want>>,
+    walked: HashSet<Cid>,
+    cache: HashMap<Cid, Vec<u8>>,
+    cached_bytes: usize,
+    records: HashMap<Cid, Vec<u8>>,
+    record_order: VecDeque<Cid>,
+    record_bytes: usize,
+    max_cached_bytes: usize,
+}
+
+impl RepoWalker {
+    /// Create a walker for the repository of a DID with the given commit root
+    pub fn new(root: Cid, did: &str, signing_key: PublicKey, max_cached_bytes: usize) -> Self {
+        Self {
+            did: did.to_string(),
+            signing_key,
+            commit: None,
+            wanted: HashMap::from([(root, vec![Want::Commit])]),
+            walked: HashSet::new(),
+            cache: HashMap::new(),
+            cached_bytes: 0,
+            records: HashMap::new(),
+            record_order: VecDeque::new(),
+            record_bytes: 0,
+            max_cached_bytes,
+        }
+    }
+
+    /// Feed a block read from the CAR file, returning all records it made available
+    pub fn push(&mut self, cid: Cid, block: Vec<u8>) -> anyhow::Result<Vec<RepoRecord>> {
+        verify_cid(&cid, &block)?;
+
+        let mut records = Vec::new();
+        if !self.wanted.contains_key(&cid) {
+            // blocks repeated in the CAR file are not needed again
+            if self.walked.contains(&cid) || self.cache.contains_key(&cid) {
+                return Ok(records);
+            }
+            self.cached_bytes += block.len();
+            if self.cached_bytes > self.max_cached_bytes {
+                return Err(RepoError::CacheExceeded {
+                    limit: self.max_cached_bytes,
+                }
+                .into());
+            }
+            self.cache.insert(cid, block);
+            return Ok(records);
+        }
+
+        let mut ready = vec![(cid, block)];
+        while let Some((cid, block)) = ready.pop() {
+            self.walked.insert(cid);
+            for want in self.wanted.remove(&cid).unwrap_or_default() {
+                match want {
+                    Want::Commit => {
+                        let commit = Commit::decode(&block, &self.did)?;
+                        commit.verify(&self.signing_key)?;
+                        self.want(commit.data, Want::Node(NodeBounds::default()), &mut ready)?;
+                        self.commit = Some(commit);
+                    }
+                    Want::Node(bounds) => {
+                        let node: NodeData =
+                            serde_ipld_dagcbor::from_slice(&block).map_err(|e| {
+                                RepoError::Corrupt(format!("invalid tree node {}: {}", cid, e))
+                            })?;
+                        self.walk_node(node, bounds, &mut ready)?;
+                    }
+                    Want::Record(key) => {
+                        self.keep_record(cid, &block);
+                        records.push(RepoRecord {
+                            key,
+                            data: block.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Ensure every referenced block was seen and return the commit
+    pub fn finish(self) -> Result<Commit, RepoError> {
+        if !self.wanted.is_empty() {
+            return Err(RepoError::Incomplete {
+                missing: self.wanted.len(),
+            });
+        }
+
+        // the root is always wanted until the commit was seen
+        Ok(self.commit.expect("commit was walked"))
+    }
+
+    /// Check the entries of a tree node and request its children and records
+    fn walk_node(
+        &mut self,
+        node: NodeData,
+        bounds: NodeBounds,
+        ready: &mut Vec<(Cid, Vec<u8>)>,
+    ) -> Result<(), RepoError> {
+        // nodes without entries only forward to their left subtree
+        if node.e.is_empty() {
+            if let Some(left) = node.l {
+                self.want(left, Want::Node(bounds), ready)?;
+            } else if bounds.parent_layer.is_some() {
+                return Err(RepoError::Corrupt(
+                    "empty tree node below the root".to_string(),
+                ));
+            }
+            return Ok(());
+        }
+
+        // decompress the keys
+        let mut keys: Vec<Vec<u8>> = Vec::with_capacity(node.e.len());
+        for entry in &node.e {
+            let previous = keys.last().map(Vec::as_slice).unwrap_or_default();
+            if entry.p as usize > previous.len() {
+                return Err(RepoError::Corrupt(
+                    "tree entry prefix exceeds the previous key".to_string(),
+                ));
+            }
+            let mut key = previous[..entry.p as usize].to_vec();
+            key.extend_from_slice(&entry.k);
+            keys.push(key);
+        }
+
+        // check the layer and ordering of the keys
+        let layer = key_layer(&keys[0]);
+        if bounds.parent_layer.is_some_and(|parent| layer >= parent) {
+            return Err(RepoError::Corrupt(format!(
+                "tree node at layer {} is not below its parent",
+                layer
+            )));
+        }
+        for (i, key) in keys.iter().enumerate() {
+            if key_layer(key) != layer {
+                return Err(RepoError::Corrupt(
+                    "tree node mixes keys of different layers".to_string(),
+                ));
+            }
+            let previous = if i == 0 {
+                bounds.lower.as_ref()
+            } else {
+                keys.get(i - 1)
+            };
+            if previous.is_some_and(|previous| key <= previous) {
+                return Err(RepoError::Corrupt("tree keys are out of order".to_string()));
+            }
+        }
+        if bounds
+            .upper
+            .as_ref()
+            .is_some_and(|upper| keys[keys.len() - 1] >= *upper)
+        {
+            return Err(RepoError::Corrupt("tree keys are out of order".to_string()));
+        }
+
+        // request the subtrees and records
+        if let Some(left) = node.l {
+            let left_bounds = NodeBounds {
+                parent_layer: Some(layer),
+                lower: bounds.lower.clone(),
+                upper: Some(keys[0].clone()),
+            };
+            self.want(left, Want::Node(left_bounds), ready)?;
+        }
+        for (i, entry) in node.e.into_iter().enumerate() {
+            if let Some(subtree) = entry.t {
+                let subtree_bounds = NodeBounds {
+                    parent_layer: Some(layer),
+                    lower: Some(keys[i].clone()),
+                    upper: keys.get(i + 1).cloned().or_else(|| bounds.upper.clone()),
+                };
+                self.want(subtree, Want::Node(subtree_bounds), ready)?;
+            }
+
+            let key = String::from_utf8(keys[i].clone())
+                .map_err(|_| RepoError::Corrupt("tree key is not valid UTF-8".to_string()))?;
+            self.want(entry.v, Want::Record(key), ready)?;
+        }
+
+        Ok(())
+    }
+
+    /// Mark a block as needed, queueing it right away if it was cached
+    fn want(
+        &mut self,
+        cid: Cid,
+        want: Want,
+        ready: &mut Vec<(Cid, Vec<u8>)>,
+    ) -> Result<(), RepoError> {
+        let pending = self.wanted.get(&cid);
+        if let Want::Commit | Want::Node(_) = want {
+            // subtrees shared by several parts of the tree are walked once
+            let seen = self.walked.contains(&cid)
+                || pending.is_some_and(|wants| wants.iter().any(|w| !matches!(w, Want::Record(_))));
+            if seen {
+                return Ok(());
+            }
+        }
+
+        if self.walked.contains(&cid) && pending.is_none() {
+            // records stored under several keys are returned once per key
+            let block = self.records.get(&cid).ok_or(RepoError::CacheExceeded {
+                limit: self.max_cached_bytes,
+            })?;
+            ready.push((cid, block.clone()));
+        } else if let Some(block) = self.cache.remove(&cid) {
+            self.cached_bytes -= block.len();
+            ready.push((cid, block));
+        }
+        self.wanted.entry(cid).or_default().push(want);
+
+        Ok(())
+    }
+
+    /// Keep a record block for later references, dropping the oldest ones beyond the cache size
+    fn keep_record(&mut self, cid: Cid, block: &[u8]) {
+        if self.records.contains_key(&cid) || block.len() > self.max_cached_bytes {
+            return;
+        }
+        self.records.insert(cid, block.to_vec());
+        self.record_order.push_back(cid);
+        self.record_bytes += block.len();
+        while self.record_bytes > self.max_cached_bytes {
+            let Some(oldest) = self.record_order.pop_front() else {
+                break;
+            };
+            if let Some(block) = self.records.remove(&oldest) {
+                self.record_bytes -= block.len();
+            }
+        }
+    }
+}
+
+/// Ensure a block hashes to its CID
+fn verify_cid(cid: &Cid, block: &[u8]) -> Result<(), RepoError> {
+    if cid.codec() != DAG_CBOR || cid.hash().code() != SHA2_256 {
+        return Err(RepoError::UnsupportedCid(*cid));
+    }
+    if cid.hash().digest() != &Sha256::digest(block)[..] {
+        return Err(RepoError::CidMismatch(*cid));
+    }
+
+    Ok(())
+}
+
+/// Layer of a key in the merkle search tree (leading zero bit pairs of its hash)
+fn key_layer(key: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for byte in Sha256::digest(key) {
+        zeros += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipld_core::cid::multihash::Multihash;
+    use k256::ecdsa::{signature::Signer, Signature, SigningKey};
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    const DID: &str = "did:plc:walkertest";
+
+    #[derive(Serialize)]
+    struct Entry {
+        #[serde(with = "serde_bytes")]
+        k: Vec<u8>,
+        p: u8,
+        t: Option<Cid>,
+        v: Cid,
+    }
+
+    #[derive(Serialize)]
+    struct Node {
+        e: Vec<Entry>,
+        l: Option<Cid>,
+    }
+
+    #[derive(Serialize)]
+    struct SignedCommit<'a> {
+        did: &'a str,
+        rev: &'a str,
+        #[serde(with = "serde_bytes")]
+        sig: Vec<u8>,
+        data: Cid,
+        prev: Option<Cid>,
+        version: u8,
+    }
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_slice(&[7; 32]).unwrap()
+    }
+
+    fn block<T: Serialize>(value: &T) -> (Cid, Vec<u8>) {
+        let bytes = serde_ipld_dagcbor::to_vec(value).unwrap();
+        let hash = Multihash::wrap(SHA2_256, &Sha256::digest(&bytes)).unwrap();
+        (Cid::new_v1(DAG_CBOR, hash), bytes)
+    }
+
+    fn record(text: &str) -> (Cid, Vec<u8>) {
+        block(&BTreeMap::from([
+            ("$type", "app.bsky.feed.like"),
+            ("text", text),
+        ]))
+    }
+
+    /// Encode a tree node, compressing each key against the previous one
+    fn node(left: Option<Cid>, entries: &[(&str, Cid, Option<Cid>)]) -> (Cid, Vec<u8>) {
+        let mut previous: &[u8] = &[];
+        let mut e = Vec::new();
+        for (key, value, subtree) in entries {
+            let key = key.as_bytes();
+            let prefix = previous.iter().zip(key).take_while(|(a, b)| a == b).count();
+            e.push(Entry {
+                k: key[prefix..].to_vec(),
+                p: prefix as u8,
+                t: *subtree,
+                v: *value,
+            });
+            previous = key;
+        }
+        block(&Node { e, l: left })
+    }
+
+    fn commit(data: Cid, key: &SigningKey) -> (Cid, Vec<u8>) {
+        let unsigned = Commit {
+            did: DID.to_string(),
+            version: 3,
+            data,
+            rev: "3l5ddo4mtmd2a".to_string(),
+            prev: None,
+            sig: Vec::new(),
+        };
+        let signature: Signature = key.sign(&unsigned.unsigned_bytes().unwrap());
+        block(&SignedCommit {
+            did: DID,
+            rev: &unsigned.rev,
+            sig: signature.to_bytes().to_vec(),
+            data,
+            prev: None,
+            version: 3,
+        })
+    }
+
+    fn new_walker(root: Cid, max_cached_bytes: usize) -> RepoWalker {
+        let key = PublicKey::K256(*signing_key().verifying_key());
+        RepoWalker::new(root, DID, key, max_cached_bytes)
+    }
+
+    /// Feed blocks in order and return the keys of all records found
+    fn walk(walker: &mut RepoWalker, blocks: &[&(Cid, Vec<u8>)]) -> Result<Vec<String>, RepoError> {
+        let mut keys = Vec::new();
+        for (cid, bytes) in blocks {
+            let records = walker
+                .push(*cid, bytes.clone())
+                .map_err(|e| e.downcast::<RepoError>().unwrap())?;
+            keys.extend(records.into_iter().map(|r| r.key));
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    #[test]
+    fn verify_cid_checks_hash_and_format() {
+        let (cid, bytes) = record("hello");
+        assert!(verify_cid(&cid, &bytes).is_ok());
+
+        let (_, other) = record("world");
+        assert!(matches!(
+            verify_cid(&cid, &other),
+            Err(RepoError::CidMismatch(_))
+        ));
+
+        let raw = Cid::new_v1(0x55, *cid.hash());
+        assert!(matches!(
+            verify_cid(&raw, &bytes),
+            Err(RepoError::UnsupportedCid(_))
+        ));
+
+        let sha512 = Multihash::wrap(0x13, &[0; 64]).unwrap();
+        let sha512 = Cid::new_v1(DAG_CBOR, sha512);
+        assert!(matches!(
+            verify_cid(&sha512, &bytes),
+            Err(RepoError::UnsupportedCid(_))
+        ));
+    }
+
+    #[test]
+    fn key_layer_matches_spec_examples() {
+        assert_eq!(key_layer(b"2653ae71"), 0);
+        assert_eq!(key_layer(b"blue"), 1);
+        assert_eq!(key_layer(b"app.bsky.feed.post/454397e440ec"), 4);
+        assert_eq!(key_layer(b"app.bsky.feed.post/9adeb165882c"), 8);
+    }
+
+    #[test]
+    fn walks_blocks_in_any_order() {
+        let (a, b, c) = (record("a"), record("b"), record("c"));
+        let tree = node(
+            None,
+            &[
+                ("app.bsky.feed.like/0", a.0, None),
+                ("app.bsky.feed.like/1", b.0, None),
+                ("app.bsky.feed.like/2", c.0, None),
+            ],
+        );
+        let root = commit(tree.0, &signing_key());
+
+        let mut walker = new_walker(root.0, 1024);
+        let keys = walk(&mut walker, &[&b, &tree, &a, &root, &c]).unwrap();
+        assert_eq!(
+            keys,
+            [
+                "app.bsky.feed.like/0",
+                "app.bsky.feed.like/1",
+                "app.bsky.feed.like/2"
+            ]
+        );
+        assert_eq!(walker.finish().unwrap().rev, "3l5ddo4mtmd2a");
+    }
+
+    #[test]
+    fn resolves_shared_subtrees_and_records() {
+        // "app.bsky.feed.like/10" is on layer 1, all other keys on layer 0
+        let (a, b, c) = (record("a"), record("b"), record("c"));
+        let left = node(None, &[("app.bsky.feed.like/0", a.0, None)]);
+        let right = node(
+            None,
+            &[
+                ("app.bsky.feed.like/2", a.0, None),
+                ("app.bsky.feed.like/3", c.0, None),
+            ],
+        );
+        let tree = node(
+            Some(left.0),
+            &[("app.bsky.feed.like/10", b.0, Some(right.0))],
+        );
+        let root = commit(tree.0, &signing_key());
+
+        // the record of the left subtree is consumed before the right subtree references it
+        let mut walker = new_walker(root.0, 1024);
+        let keys = walk(&mut walker, &[&root, &tree, &left, &a, &b, &right, &c]).unwrap();
+        assert_eq!(
+            keys,
+            [
+                "app.bsky.feed.like/0",
+                "app.bsky.feed.like/10",
+                "app.bsky.feed.like/2",
+                "app.bsky.feed.like/3"
+            ]
+        );
+        assert!(walker.finish().is_ok());
+
+        // a subtree referenced twice is walked once
+        let tree = node(
+            Some(left.0),
+            &[("app.bsky.feed.like/10", b.0, Some(left.0))],
+        );
+        let root = commit(tree.0, &signing_key());
+        let mut walker = new_walker(root.0, 1024);
+        let keys = walk(&mut walker, &[&root, &tree, &left, &a, &b]).unwrap();
+        assert_eq!(keys, ["app.bsky.feed.like/0", "app.bsky.feed.like/10"]);
+        assert!(walker.finish().is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_trees() {
+        let (a, b) = (record("a"), record("b"));
+
+        // keys out of order
+        let tree = node(
+            None,
+            &[
+                ("app.bsky.feed.like/1", a.0, None),
+                ("app.bsky.feed.like/0", b.0, None),
+            ],
+        );
+        let root = commit(tree.0, &signing_key());
+        let res = walk(&mut new_walker(root.0, 1024), &[&root, &tree]);
+        assert!(matches!(res, Err(RepoError::Corrupt(_))));
+
+        // keys of different layers in one node
+        let tree = node(
+            None,
+            &[
+                ("app.bsky.feed.like/0", a.0, None),
+                ("app.bsky.feed.like/10", b.0, None),
+            ],
+        );
+        let root = commit(tree.0, &signing_key());
+        let res = walk(&mut new_walker(root.0, 1024), &[&root, &tree]);
+        assert!(matches!(res, Err(RepoError::Corrupt(_))));
+
+        // subtree keys outside of the range given by the parent
+        let right = node(None, &[("app.bsky.feed.like/0", a.0, None)]);
+        let tree = node(None, &[("app.bsky.feed.like/10", b.0, Some(right.0))]);
+        let root = commit(tree.0, &signing_key());
+        let res = walk(&mut new_walker(root.0, 1024), &[&root, &tree, &right]);
+        assert!(matches!(res, Err(RepoError::Corrupt(_))));
+    }
+
+    #[test]
+    fn rejects_foreign_signatures() {
+        let a = record("a");
+        let tree = node(None, &[("app.bsky.feed.like/0", a.0, None)]);
+        let root = commit(tree.0, &SigningKey::from_slice(&[8; 32]).unwrap());
+        let res = walk(&mut new_walker(root.0, 1024), &[&a, &tree, &root]);
+        assert!(matches!(res, Err(RepoError::InvalidSignature)));
+    }
+
+    #[test]
+    fn reports_missing_blocks_and_cache_overflow() {
+        let (a, b) = (record("a"), record("b"));
+        let tree = node(
+            None,
+            &[
+                ("app.bsky.feed.like/0", a.0, None),
+                ("app.bsky.feed.like/1", b.0, None),
+            ],
+        );
+        let root = commit(tree.0, &signing_key());
+
+        let mut incomplete = new_walker(root.0, 1024);
+        walk(&mut incomplete, &[&root, &tree, &a]).unwrap();
+        assert!(matches!(
+            incomplete.finish(),
+            Err(RepoError::Incomplete { missing: 1 })
+        ));
+
+        let res = walk(&mut new_walker(root.0, a.1.len()), &[&a, &b]);
+        assert!(matches!(res, Err(RepoError::CacheExceeded { .. })));
+    }
+}
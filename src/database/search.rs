@@ -0,0 +1,157 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use surrealdb::{engine::any::Any, RecordId, Surreal};
+
+/// Languages with a dedicated stemming index, their post field and index
+const POST_LANGUAGES: &[(&str, &str, &str)] = &[
+    ("en", "textEn", "post_text_search_en"),
+    ("de", "textDe", "post_text_search_de"),
+    ("fr", "textFr", "post_text_search_fr"),
+    ("es", "textEs", "post_text_search_es"),
+    ("pt", "textPt", "post_text_search_pt"),
+    ("nl", "textNl", "post_text_search_nl"),
+    ("it", "textIt", "post_text_search_it"),
+];
+
+/// Search indexes on the display name and description of profiles
+const PROFILE_INDEXES: &[&str] = &["did_display_name_search", "did_description_search"];
+
+/// A post matching a full-text search
+#[derive(Debug, Deserialize)]
+pub struct PostHit {
+    pub id: RecordId,
+    pub score: f64,
+    pub highlight: Option<String>,
+}
+
+/// A profile matching a full-text search
+#[derive(Debug, Deserialize)]
+pub struct ProfileHit {
+    pub id: RecordId,
+    pub score: f64,
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
+    #[serde(rename = "displayNameHighlight")]
+    pub display_name_highlight: Option<String>,
+    #[serde(rename = "descriptionHighlight")]
+    pub description_highlight: Option<String>,
+}
+
+/// Search posts, using the stemming index of the language if there is one
+pub async fn search_posts(
+    db: &Surreal<Any>,
+    query: &str,
+    lang: Option<&str>,
+    limit: usize,
+) -> Result<Vec<PostHit>> {
+    let (field, index) = post_field(lang);
+    require_indexes(db, "post", &[index]).await?;
+
+    let mut res = db
+        .query(format!(
+            "SELECT id, search::score(1) AS score, search::highlight('<b>', '</b>', 1) AS highlight
+            FROM post WHERE {} @1@ $query ORDER BY score DESC LIMIT $limit;",
+            field
+        ))
+        .bind(("query", query.to_string()))
+        .bind(("limit", limit))
+        .await?;
+    let hits: Vec<PostHit> = res.take(0)?;
+
+    Ok(hits)
+}
+
+/// Search profiles by display name and description
+pub async fn search_profiles(
+    db: &Surreal<Any>,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<ProfileHit>> {
+    require_indexes(db, "did", PROFILE_INDEXES).await?;
+
+    let mut res = db
+        .query(
+            "SELECT id, displayName,
+                search::score(1) + search::score(2) AS score,
+                search::highlight('<b>', '</b>', 1) AS displayNameHighlight,
+                search::highlight('<b>', '</b>', 2) AS descriptionHighlight
+            FROM did WHERE displayName @1@ $query OR description @2@ $query
+            ORDER BY score DESC LIMIT $limit;",
+        )
+        .bind(("query", query.to_string()))
+        .bind(("limit", limit))
+        .await?;
+    let hits: Vec<ProfileHit> = res.take(0)?;
+
+    Ok(hits)
+}
+
+/// Get the post field and search index to use for a language
+fn post_field(lang: Option<&str>) -> (&'static str, &'static str) {
+    lang.map(|l| l.split('-').next().unwrap_or(l).to_lowercase())
+        .and_then(|l| POST_LANGUAGES.iter().find(|(code, _, _)| *code == l))
+        .map_or(("text", "post_text_search"), |(_, field, index)| {
+            (*field, *index)
+        })
+}
+
+/// Fail unless the given search indexes are defined on a table
+async fn require_indexes(db: &Surreal<Any>, table: &str, indexes: &[&str]) -> Result<()> {
+    let mut res = db.query(format!("INFO FOR TABLE {};", table)).await?;
+    let defined: Option<BTreeMap<String, String>> = res.take((0, "indexes"))?;
+    let defined = defined.unwrap_or_default();
+
+    let missing: Vec<&str> = indexes
+        .iter()
+        .copied()
+        .filter(|index| !defined.contains_key(*index))
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "Search index {} is not defined, start the indexer without --skip-expensive-indexes \
+            and without --disable-index {} to build it",
+            missing.join(", "),
+            missing.join(" --disable-index ")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::indexes::INDEXES;
+
+    #[test]
+    fn picks_the_stemming_index_of_the_language() {
+        assert_eq!(post_field(Some("en")), ("textEn", "post_text_search_en"));
+        assert_eq!(post_field(Some("pt-BR")), ("textPt", "post_text_search_pt"));
+        assert_eq!(post_field(Some("DE")), ("textDe", "post_text_search_de"));
+        assert_eq!(post_field(Some("ja")), ("text", "post_text_search"));
+        assert_eq!(post_field(None), ("text", "post_text_search"));
+    }
+
+    #[test]
+    fn searched_indexes_are_managed() {
+        let searched = POST_LANGUAGES
+            .iter()
+            .map(|(_, field, index)| (*index, "post", *field))
+            .chain([("post_text_search", "post", "text")])
+            .chain([
+                ("did_display_name_search", "did", "displayName"),
+                ("did_description_search", "did", "description"),
+            ]);
+        for (name, table, field) in searched {
+            let index = INDEXES
+                .iter()
+                .find(|i| i.name == name)
+                .unwrap_or_else(|| panic!("index {} is not managed", name));
+            assert_eq!(index.table, table);
+            assert!(index
+                .definition
+                .starts_with(&format!("FIELDS {} SEARCH", field)));
+        }
+    }
+}
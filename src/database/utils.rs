@@ -1,5 +1,6 @@
 use ::atrium_api::{
-    com::atproto::repo::strong_ref::Main,
+    app::bsky::richtext::facet::{Main as Facet, MainFeaturesItem},
+    com::atproto::{label::defs::SelfLabels, repo::strong_ref::Main},
     types::{string::RecordKey, BlobRef, TypedBlobRef, Union},
 };
 use anyhow::{Context, Result};
@@ -8,6 +9,8 @@ use lazy_static::lazy_static;
 use regex::Regex;
 use surrealdb::RecordId;
 
+use super::definitions::BskyFacets;
+
 lazy_static! {
     static ref VALID_DID_KEY_REGEX: Regex = Regex::new(r"^(plc|web)_[a-z0-9_]+$").unwrap();
 }
@@ -20,49 +23,73 @@ pub fn extract_dt(dt: &atrium_api::Datetime) -> Result<surrealdb::Datetime> {
         .into())
 }
 
-/// Extracts the self labels from a profile record labels refs
-pub fn extract_self_labels_profile(
-    labels: &Union<::atrium_api::app::bsky::actor::profile::RecordLabelsRefs>,
-) -> Option<Vec<String>> {
-    match labels {
-        Union::Refs(refs) => match refs {
-            ::atrium_api::app::bsky::actor::profile::RecordLabelsRefs::ComAtprotoLabelDefsSelfLabels(labels) => {
-                Some(labels.values.iter().map(|l| l.val.clone()).collect())
-            }
-        },
-        Union::Unknown(_) => None,
-    }
+/// Record labels refs carrying `com.atproto.label.defs#selfLabels`
+pub trait SelfLabelsRefs {
+    /// Returns the self labels held by the refs
+    fn self_labels(&self) -> &SelfLabels;
 }
 
-/// Extracts the self labels from a list record labels refs
-pub fn extract_self_labels_list(
-    labels: &Union<::atrium_api::app::bsky::graph::list::RecordLabelsRefs>,
-) -> Option<Vec<String>> {
-    match labels {
-        Union::Refs(refs) => match refs {
-            ::atrium_api::app::bsky::graph::list::RecordLabelsRefs::ComAtprotoLabelDefsSelfLabels(labels) => {
-                Some(labels.values.iter().map(|l| l.val.clone()).collect())
+/// Implements [`SelfLabelsRefs`] for the labels refs of the given lexicons
+macro_rules! impl_self_labels_refs {
+    ($($refs:ty),* $(,)?) => {
+        $(
+            impl SelfLabelsRefs for $refs {
+                fn self_labels(&self) -> &SelfLabels {
+                    match self {
+                        Self::ComAtprotoLabelDefsSelfLabels(labels) => labels,
+                    }
+                }
             }
-        },
-        Union::Unknown(_) => None,
-    }
+        )*
+    };
 }
 
+impl_self_labels_refs!(
+    ::atrium_api::app::bsky::actor::profile::RecordLabelsRefs,
+    ::atrium_api::app::bsky::feed::generator::RecordLabelsRefs,
+    ::atrium_api::app::bsky::feed::post::RecordLabelsRefs,
+    ::atrium_api::app::bsky::graph::list::RecordLabelsRefs,
+    ::atrium_api::app::bsky::labeler::service::RecordLabelsRefs,
+);
+
 /// Extracts the self labels from a record labels refs
-pub fn extract_self_labels_post(
-    labels: &Union<::atrium_api::app::bsky::feed::post::RecordLabelsRefs>,
-) -> Option<Vec<String>> {
+pub fn extract_self_labels<T: SelfLabelsRefs>(labels: &Union<T>) -> Option<Vec<String>> {
     match labels {
-        Union::Refs(refs) => match refs {
-            ::atrium_api::app::bsky::feed::post::RecordLabelsRefs::ComAtprotoLabelDefsSelfLabels(labels) => {
-                Some(labels.values.iter().map(|l| l.val.clone()).collect())
-            }
-        },
+        Union::Refs(refs) => Some(
+            refs.self_labels()
+                .values
+                .iter()
+                .map(|l| l.val.clone())
+                .collect(),
+        ),
         Union::Unknown(_) => None,
     }
 }
 
-// TODO self labels for feed generators and labeller services
+/// Extracts the mentions, links and tags from richtext facets
+pub fn extract_facets(facets: &[Facet]) -> Result<BskyFacets> {
+    let mut res = BskyFacets::default();
+    for facet in facets {
+        for feature in &facet.features {
+            match feature {
+                Union::Refs(refs) => match refs {
+                    MainFeaturesItem::Mention(m) => {
+                        res.mentions
+                            .push(("did", did_to_key(m.did.as_str())?).into());
+                    }
+                    MainFeaturesItem::Link(l) => {
+                        res.links.push(l.uri.clone());
+                    }
+                    MainFeaturesItem::Tag(t) => {
+                        res.tags.push(t.tag.clone());
+                    }
+                },
+                Union::Unknown(_) => {}
+            }
+        }
+    }
+    Ok(res)
+}
 
 /// Converts a DID to a key
 pub fn did_to_key(did: &str) -> Result<String> {
@@ -0,0 +1,224 @@
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use simd_json::OwnedValue;
+
+use super::key::PublicKey;
+
+/// A resolved DID document
+///
+/// Entries of other DID methods or with unexpected shapes are skipped
+/// instead of rejecting the whole document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DidDocument {
+    pub id: String,
+    #[serde(rename = "alsoKnownAs", default, deserialize_with = "skip_invalid")]
+    pub also_known_as: Vec<String>,
+    #[serde(
+        rename = "verificationMethod",
+        default,
+        deserialize_with = "skip_invalid"
+    )]
+    pub verification_method: Vec<VerificationMethod>,
+    #[serde(default, deserialize_with = "skip_invalid")]
+    pub service: Vec<Service>,
+}
+
+/// A verification method declared in a DID document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationMethod {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[serde(rename = "publicKeyMultibase")]
+    pub public_key_multibase: Option<String>,
+}
+
+/// A service declared in a DID document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Service {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[serde(rename = "serviceEndpoint")]
+    pub service_endpoint: ServiceEndpoint,
+}
+
+/// Endpoint of a service, either a URI or a map or set of them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ServiceEndpoint {
+    Uri(String),
+    Other(OwnedValue),
+}
+
+impl DidDocument {
+    /// Handles claimed by the DID
+    pub fn handles(&self) -> Vec<&str> {
+        self.also_known_as
+            .iter()
+            .filter_map(|aka| aka.strip_prefix("at://"))
+            .collect()
+    }
+
+    /// Key the repository of the DID is signed with
+    pub fn signing_key(&self) -> Result<PublicKey> {
+        let method = self
+            .verification_method
+            .iter()
+            .find(|method| method.id.ends_with("#atproto"))
+            .context("No #atproto verification method")?;
+        let public_key = method
+            .public_key_multibase
+            .as_deref()
+            .context("The #atproto verification method has no publicKeyMultibase")?;
+
+        PublicKey::from_verification_method(&method.type_, public_key)
+    }
+
+    /// Endpoint of the personal data server hosting the repository
+    pub fn pds_endpoint(&self) -> Option<&str> {
+        // the id is authoritative, the type only disambiguates documents without it
+        self.service
+            .iter()
+            .find(|s| s.id.ends_with("#atproto_pds"))
+            .or_else(|| {
+                self.service
+                    .iter()
+                    .find(|s| s.type_ == "AtprotoPersonalDataServer")
+            })
+            .and_then(|s| match &s.service_endpoint {
+                ServiceEndpoint::Uri(uri) => Some(uri.trim_end_matches('/')),
+                ServiceEndpoint::Other(_) => None,
+            })
+    }
+}
+
+/// Deserialize a list, dropping the entries that do not parse
+fn skip_invalid<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let entries = Vec::<OwnedValue>::deserialize(deserializer)?;
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| simd_json::serde::from_owned_value(entry).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLC_DOCUMENT: &str = r##"{
+        "@context": [
+            "https://www.w3.org/ns/did/v1",
+            "https://w3id.org/security/multikey/v1",
+            "https://w3id.org/security/suites/secp256k1-2019/v1"
+        ],
+        "id": "did:plc:ewvi7nxzyoun6zhxrhs64oiz",
+        "alsoKnownAs": ["at://atproto.com"],
+        "verificationMethod": [
+            {
+                "id": "did:plc:ewvi7nxzyoun6zhxrhs64oiz#atproto",
+                "type": "Multikey",
+                "controller": "did:plc:ewvi7nxzyoun6zhxrhs64oiz",
+                "publicKeyMultibase": "zQ3shjyJXUaRJC2GC43mX8aPrUhoTdoiongXhZjsdTzPKYZUM"
+            }
+        ],
+        "service": [
+            {
+                "id": "#atproto_pds",
+                "type": "AtprotoPersonalDataServer",
+                "serviceEndpoint": "https://enoki.us-east.host.bsky.network"
+            }
+        ]
+    }"##;
+
+    const WEB_DOCUMENT: &str = r##"{
+        "@context": ["https://www.w3.org/ns/did/v1"],
+        "id": "did:web:example.com",
+        "alsoKnownAs": ["at://example.com", 42],
+        "verificationMethod": [
+            {
+                "id": "did:web:example.com#key-1",
+                "type": "JsonWebKey2020",
+                "controller": "did:web:example.com",
+                "publicKeyJwk": { "kty": "EC", "crv": "P-256", "x": "f83O", "y": "x_FE" }
+            },
+            {
+                "id": "did:web:example.com#atproto",
+                "type": "Multikey",
+                "controller": "did:web:example.com",
+                "publicKeyMultibase": "zDnaex62me84JZnkEzmeYRa8FCLNe7y1asoSwBMK26GBYpL7c"
+            },
+            { "type": "Multikey" }
+        ],
+        "service": [
+            {
+                "id": "#hub",
+                "type": "LinkedDomains",
+                "serviceEndpoint": { "origins": ["https://example.com"] }
+            },
+            {
+                "id": "#mirrors",
+                "type": "Mirror",
+                "serviceEndpoint": ["https://a.example.com", "https://b.example.com"]
+            },
+            {
+                "id": "did:web:example.com#atproto_pds",
+                "type": "AtprotoPersonalDataServer",
+                "serviceEndpoint": "https://pds.example.com/",
+                "description": "extra fields are ignored"
+            },
+            { "serviceEndpoint": "https://broken.example.com" }
+        ]
+    }"##;
+
+    fn parse(json: &str) -> DidDocument {
+        simd_json::serde::from_slice(&mut json.as_bytes().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn parses_plc_documents() {
+        let document = parse(PLC_DOCUMENT);
+        assert_eq!(document.handles(), ["atproto.com"]);
+        assert!(matches!(document.signing_key(), Ok(PublicKey::K256(_))));
+        assert_eq!(
+            document.pds_endpoint(),
+            Some("https://enoki.us-east.host.bsky.network")
+        );
+    }
+
+    #[test]
+    fn parses_web_documents_leniently() {
+        let document = parse(WEB_DOCUMENT);
+        assert_eq!(document.handles(), ["example.com"]);
+        assert_eq!(document.verification_method.len(), 2);
+        assert_eq!(document.service.len(), 3);
+        assert!(matches!(document.signing_key(), Ok(PublicKey::P256(_))));
+        assert_eq!(document.pds_endpoint(), Some("https://pds.example.com"));
+
+        // cached documents are read back the same way
+        let cached = parse(&simd_json::serde::to_string(&document).unwrap());
+        assert_eq!(cached.service.len(), 3);
+        assert_eq!(cached.pds_endpoint(), Some("https://pds.example.com"));
+    }
+
+    #[test]
+    fn reports_missing_keys_and_endpoints() {
+        let document = parse(
+            r##"{
+                "id": "did:web:example.com",
+                "verificationMethod": [
+                    { "id": "#atproto", "type": "JsonWebKey2020", "publicKeyJwk": {} }
+                ],
+                "service": [
+                    { "id": "#atproto_pds", "type": "AtprotoPersonalDataServer", "serviceEndpoint": {} }
+                ]
+            }"##,
+        );
+        assert!(document.signing_key().is_err());
+        assert_eq!(document.pds_endpoint(), None);
+    }
+}
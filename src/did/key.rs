@@ -0,0 +1,116 @@
+use anyhow::Result;
+use k256::ecdsa::signature::Verifier;
+use multibase::Base;
+
+/// Multicodec prefix of a compressed secp256k1 public key
+const SECP256K1_PUB: [u8; 2] = [0xe7, 0x01];
+/// Multicodec prefix of a compressed p256 public key
+const P256_PUB: [u8; 2] = [0x80, 0x24];
+
+/// Public key repository commits are signed with
+#[derive(Debug)]
+pub enum PublicKey {
+    K256(k256::ecdsa::VerifyingKey),
+    P256(p256::ecdsa::VerifyingKey),
+}
+
+impl PublicKey {
+    /// Decode the key of a DID document verification method
+    pub fn from_verification_method(type_: &str, public_key_multibase: &str) -> Result<Self> {
+        let (base, bytes) = multibase::decode(public_key_multibase)?;
+        if base != Base::Base58Btc {
+            anyhow::bail!("Unexpected multibase encoding {:?}", base);
+        }
+
+        let key = match type_ {
+            "Multikey" => match (bytes.get(..2), bytes.get(2..)) {
+                (Some(prefix), Some(key)) if prefix == SECP256K1_PUB => {
+                    k256::ecdsa::VerifyingKey::from_sec1_bytes(key).map(PublicKey::K256)
+                }
+                (Some(prefix), Some(key)) if prefix == P256_PUB => {
+                    p256::ecdsa::VerifyingKey::from_sec1_bytes(key).map(PublicKey::P256)
+                }
+                _ => anyhow::bail!("Unsupported multikey type"),
+            },
+            // legacy verification methods carry the bare key
+            "EcdsaSecp256k1VerificationKey2019" => {
+                k256::ecdsa::VerifyingKey::from_sec1_bytes(&bytes).map(PublicKey::K256)
+            }
+            "EcdsaSecp256r1VerificationKey2019" => {
+                p256::ecdsa::VerifyingKey::from_sec1_bytes(&bytes).map(PublicKey::P256)
+            }
+            _ => anyhow::bail!("Unsupported verification method type {}", type_),
+        };
+
+        Ok(key?)
+    }
+
+    /// Check a compact low-S signature over a message
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            PublicKey::K256(key) => {
+                let Ok(signature) = k256::ecdsa::Signature::from_slice(signature) else {
+                    return false;
+                };
+                signature.normalize_s().is_none() && key.verify(message, &signature).is_ok()
+            }
+            PublicKey::P256(key) => {
+                let Ok(signature) = p256::ecdsa::Signature::from_slice(signature) else {
+                    return false;
+                };
+                signature.normalize_s().is_none() && key.verify(message, &signature).is_ok()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_multikeys() {
+        let k256 = "zQ3shjyJXUaRJC2GC43mX8aPrUhoTdoiongXhZjsdTzPKYZUM";
+        let p256 = "zDnaex62me84JZnkEzmeYRa8FCLNe7y1asoSwBMK26GBYpL7c";
+        assert!(matches!(
+            PublicKey::from_verification_method("Multikey", k256),
+            Ok(PublicKey::K256(_))
+        ));
+        assert!(matches!(
+            PublicKey::from_verification_method("Multikey", p256),
+            Ok(PublicKey::P256(_))
+        ));
+
+        // legacy methods carry the same key without the multicodec prefix
+        let legacy = "zz29WdRwpsP3Jox66kVWof7S1z3XqW8VAdp7qAeiCL5cd";
+        assert!(matches!(
+            PublicKey::from_verification_method("EcdsaSecp256k1VerificationKey2019", legacy),
+            Ok(PublicKey::K256(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_multikeys() {
+        let k256 = "zQ3shjyJXUaRJC2GC43mX8aPrUhoTdoiongXhZjsdTzPKYZUM";
+        let (_, bytes) = multibase::decode(k256).unwrap();
+
+        // not multibase at all
+        assert!(PublicKey::from_verification_method("Multikey", "Q3shjyJXUaRJC2GC43").is_err());
+        // multibase other than base58btc
+        let hex = multibase::encode(Base::Base16Lower, &bytes);
+        assert!(PublicKey::from_verification_method("Multikey", &hex).is_err());
+        // ed25519 keys are not allowed in repositories
+        let mut ed25519 = vec![0xed, 0x01];
+        ed25519.extend_from_slice(&[1; 32]);
+        let ed25519 = multibase::encode(Base::Base58Btc, &ed25519);
+        assert!(PublicKey::from_verification_method("Multikey", &ed25519).is_err());
+        // truncated key
+        let truncated = multibase::encode(Base::Base58Btc, &bytes[..20]);
+        assert!(PublicKey::from_verification_method("Multikey", &truncated).is_err());
+        // prefix only
+        let prefix = multibase::encode(Base::Base58Btc, SECP256K1_PUB);
+        assert!(PublicKey::from_verification_method("Multikey", &prefix).is_err());
+        // unknown verification method
+        assert!(PublicKey::from_verification_method("JsonWebKey2020", k256).is_err());
+    }
+}
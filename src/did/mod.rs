@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use log::debug;
+use reqwest::Client;
+use surrealdb::{engine::any::Any, Surreal};
+
+use crate::database::utils::did_to_key;
+
+mod document;
+pub mod key;
+
+pub use document::DidDocument;
+
+/// Resolves DIDs to their documents, caching them in the database
+#[derive(Debug)]
+pub struct DidResolver {
+    db: Surreal<Any>,
+    http_client: Client,
+    plc_directory: String,
+    cache_ttl: u64,
+}
+
+impl DidResolver {
+    /// Create a resolver using the given PLC directory and cache TTL in seconds
+    pub fn new(db: Surreal<Any>, plc_directory: &str, cache_ttl: u64) -> Self {
+        Self {
+            db,
+            http_client: Client::new(),
+            plc_directory: plc_directory.trim_end_matches('/').to_string(),
+            cache_ttl,
+        }
+    }
+
+    /// Resolve a DID, using the cached document if it is fresh enough
+    pub async fn resolve(&self, did: &str) -> Result<DidDocument> {
+        let did_key = did_to_key(did)?;
+        let mut res = self
+            .db
+            .query(format!(
+                "SELECT VALUE document FROM ONLY did_cache:{} WHERE fetchedAt > time::now() - {}s;",
+                did_key, self.cache_ttl
+            ))
+            .await?;
+        let cached: Option<DidDocument> = res.take(0)?;
+        if let Some(document) = cached {
+            return Ok(document);
+        }
+
+        debug!(target: "indexer", "Resolving {}", did);
+        let url = if did.starts_with("did:plc:") {
+            format!("{}/{}", self.plc_directory, did)
+        } else if let Some(host) = did.strip_prefix("did:web:") {
+            // only hostnames are allowed, ports are percent-encoded
+            if host.contains(':') {
+                anyhow::bail!("did:web with a path is not supported: {}", did);
+            }
+            format!("https://{}/.well-known/did.json", host.replace("%3A", ":"))
+        } else {
+            anyhow::bail!("Unsupported DID method: {}", did);
+        };
+
+        let document = self
+            .http_client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<DidDocument>()
+            .await
+            .with_context(|| format!("Failed to fetch DID document from {}", url))?;
+        if document.id != did {
+            anyhow::bail!("DID document for {} belongs to {}", did, document.id);
+        }
+
+        self.db
+            .query(format!(
+                "UPSERT did_cache:{} CONTENT {{ document: $document, fetchedAt: time::now() }};",
+                did_key
+            ))
+            .bind(("document", document.clone()))
+            .await?
+            .check()?;
+
+        Ok(document)
+    }
+    /// Drop the cached document of a DID, so the next resolve fetches it again
+    pub async fn invalidate(&self, did: &str) -> Result<()> {
+        let did_key = did_to_key(did)?;
+        self.db
+            .query(format!("DELETE did_cache:{};", did_key))
+            .await?
+            .check()?;
+
+        Ok(())
+    }
+}
@@ -2,14 +2,19 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 
 use ::log::{error, info};
 use anyhow::Context;
-use config::Args;
-use database::repo_indexer::start_full_repo_indexer;
+use config::{Args, Command, MigrateAction, SearchTarget};
+use database::{
+    indexes::IndexOptions,
+    repo_indexer::{seed::SeedOptions, start_full_repo_indexer, RepoIndexerOptions},
+};
+use did::DidResolver;
 use surrealdb::{engine::any::Any, Surreal};
 use tokio::runtime::Builder;
 use tokio_rustls::rustls::crypto::aws_lc_rs::default_provider;
 
 mod config;
 mod database;
+mod did;
 mod log;
 mod websocket;
 
@@ -67,10 +72,60 @@ fn main() {
 
 /// Asynchronous main function
 async fn application_main(args: Args) -> anyhow::Result<()> {
+    database::handlers::set_post_delete_cascade(args.post_delete_cascade);
+    // only the full repo indexer re-crawls lagging repos
+    database::handlers::set_track_repo_rev(args.recrawl_lagging && args.mode == "full");
+
     // connect to the database
-    let db = database::connect(args.db)
-        .await
-        .context("Failed to connect to the database")?;
+    let db = database::connect(
+        args.db.clone(),
+        &args.db_namespace,
+        &args.db_database,
+        args.db_credentials()?,
+    )
+    .await
+    .context("Failed to connect to the database")?;
+
+    let index_options = IndexOptions {
+        skip_expensive: args.skip_expensive_indexes,
+        disabled: args.disable_index.clone(),
+    };
+
+    // run maintenance commands instead of the indexer
+    match &args.command {
+        Some(Command::Migrate {
+            action: MigrateAction::Up { dry_run },
+        }) => {
+            database::migrate(&db, *dry_run).await?;
+            return database::sync_indexes(&db, &index_options, *dry_run).await;
+        }
+        Some(Command::Migrate {
+            action: MigrateAction::Status,
+        }) => {
+            return database::migrations::status(&db)
+                .await
+                .context("Failed to fetch migration status");
+        }
+        _ => {}
+    }
+
+    // bring the database schema up to date
+    database::migrate(&db, false).await?;
+
+    // maintenance commands leave the indexes alone, building them can take hours
+    match &args.command {
+        Some(Command::RepairCounters) => {
+            return database::counters::repair(&db)
+                .await
+                .context("Failed to repair counters");
+        }
+        Some(Command::Search { target }) => {
+            return search(&db, target).await.context("Search failed");
+        }
+        _ => {}
+    }
+
+    database::sync_indexes(&db, &index_options, false).await?;
 
     let jetstream_hosts = vec![
         "jetstream1.us-west.bsky.network",
@@ -122,13 +177,60 @@ async fn application_main(args: Args) -> anyhow::Result<()> {
     });
 
     if args.mode == "full" {
-        start_full_repo_indexer(db, args.max_concurrent_requests.unwrap_or(num_cpus::get() * 50)).await?;
+        let options = RepoIndexerOptions {
+            max_concurrent_requests: args.max_concurrent_requests.unwrap_or(num_cpus::get() * 50),
+            block_cache_size: args.repo_block_cache,
+            resolver: DidResolver::new(db.clone(), &args.plc_directory, args.did_cache_ttl),
+            seed: SeedOptions {
+                sources: args.backfill_seed.clone(),
+                file: args.seed_file.clone(),
+                pds: args.seed_pds.clone(),
+                plc_directory: args.plc_directory.clone(),
+            },
+            recrawl_after: args.recrawl_after,
+            recrawl_lagging: args.recrawl_lagging,
+        };
+        start_full_repo_indexer(db, options).await?;
     }
 
     loop {
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
     }
 }
+
+/// Run a full-text search and print the ranked results
+async fn search(db: &Surreal<Any>, target: &SearchTarget) -> anyhow::Result<()> {
+    match target {
+        SearchTarget::Posts { query, lang, limit } => {
+            let hits = database::search::search_posts(db, query, lang.as_deref(), *limit).await?;
+            for hit in hits {
+                println!(
+                    "{:.3} {} {}",
+                    hit.score,
+                    hit.id,
+                    hit.highlight.unwrap_or_default()
+                );
+            }
+        }
+        SearchTarget::Profiles { query, limit } => {
+            let hits = database::search::search_profiles(db, query, *limit).await?;
+            for hit in hits {
+                println!(
+                    "{:.3} {} {} {}",
+                    hit.score,
+                    hit.id,
+                    hit.display_name_highlight
+                        .or(hit.display_name)
+                        .unwrap_or_default(),
+                    hit.description_highlight.unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
 async fn start_jetstream_consumer(
     db: Surreal<Any>,
     host: String,